@@ -0,0 +1,377 @@
+use anyhow::Result;
+use chrono::NaiveDate;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+
+use crate::external_models::{
+    DateKind, DateOrder, ExternalTransaction, IssueKind, RowIssue, Statement, StatementBalance,
+    StatementFormat,
+};
+
+// ISO 20022 bank to customer statement (camt.053) and account report (camt.052).
+pub struct CamtFormat;
+
+#[derive(Default)]
+struct EntryBuilder {
+    amount: Option<f64>,
+    credit: Option<bool>,
+    status: Option<String>,
+    booking_date: Option<NaiveDate>,
+    value_date: Option<NaiveDate>,
+    entry_ref: Option<String>,
+    servicer_ref: Option<String>,
+    end_to_end_id: Option<String>,
+    debtor_name: Option<String>,
+    debtor_account: Option<String>,
+    creditor_name: Option<String>,
+    creditor_account: Option<String>,
+    remittance: Vec<String>,
+    additional_info: Option<String>,
+    total_charges: Option<f64>,
+    charge_records: Option<f64>,
+}
+
+#[derive(Default)]
+struct BalanceBuilder {
+    code: Option<String>,
+    amount: Option<f64>,
+    credit: Option<bool>,
+    date: Option<NaiveDate>,
+}
+
+impl StatementFormat for CamtFormat {
+    // Like MT940, the remittance information can mention unrelated dates
+    fn date_order(&self) -> DateOrder {
+        DateOrder(vec![DateKind::Booking, DateKind::Value])
+    }
+
+    fn parse_statement(&self, content: &str) -> Result<Statement> {
+        let mut reader = Reader::from_str(content);
+        reader.trim_text(true);
+
+        let mut statement = Statement::default();
+        let mut path: Vec<String> = Vec::new();
+        let mut text = String::new();
+        let mut entry: Option<EntryBuilder> = None;
+        let mut balance: Option<BalanceBuilder> = None;
+        let mut entry_count = 0;
+
+        loop {
+            match reader.read_event()? {
+                Event::Start(element) => {
+                    let name = String::from_utf8_lossy(element.local_name().as_ref()).into_owned();
+                    match name.as_str() {
+                        "Ntry" => {
+                            entry_count += 1;
+                            entry = Some(EntryBuilder::default())
+                        }
+                        "Bal" if entry.is_none() => balance = Some(BalanceBuilder::default()),
+                        _ => {}
+                    }
+                    path.push(name);
+                    text.clear();
+                }
+                Event::Text(value) => text.push_str(&value.unescape()?),
+                Event::CData(value) => text.push_str(&String::from_utf8_lossy(&value)),
+                Event::End(_) => {
+                    let value = text.trim();
+                    if value.is_empty() {
+                        // container elements, nothing to store
+                    } else if let Some(current) = entry.as_mut() {
+                        current.apply(&path, value);
+                    } else if let Some(current) = balance.as_mut() {
+                        current.apply(&path, value);
+                    }
+                    text.clear();
+                    match path.pop().as_deref() {
+                        Some("Ntry") => {
+                            if let Some(finished) = entry.take() {
+                                finished.store(entry_count, &mut statement);
+                            }
+                        }
+                        Some("Bal") if entry.is_none() => {
+                            if let Some(finished) = balance.take() {
+                                finished.store(&mut statement);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+        if path.is_empty() {
+            Ok(statement)
+        } else {
+            Err(anyhow!(
+                "Unexpected end of the camt document at {}",
+                path.join("/")
+            ))
+        }
+    }
+}
+
+fn ends_with(path: &[String], suffix: &[&str]) -> bool {
+    path.len() >= suffix.len()
+        && path[path.len() - suffix.len()..]
+            .iter()
+            .zip(suffix)
+            .all(|(element, expected)| element == expected)
+}
+
+fn parse_amount(value: &str) -> Option<f64> {
+    value.parse().ok()
+}
+
+// Both <Dt>2023-01-31</Dt> and <DtTm>2023-01-31T10:00:00</DtTm> are allowed.
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    value
+        .get(0..10)
+        .and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok())
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    if value.is_empty() || value == "NOTPROVIDED" {
+        None
+    } else {
+        Some(value.to_owned())
+    }
+}
+
+fn is_date(path: &[String], parent: &str) -> bool {
+    ends_with(path, &[parent, "Dt"]) || ends_with(path, &[parent, "DtTm"])
+}
+
+impl EntryBuilder {
+    fn apply(&mut self, path: &[String], value: &str) {
+        let in_details = path.iter().any(|element| element == "NtryDtls");
+        if ends_with(path, &["Ntry", "Amt"]) {
+            self.amount = parse_amount(value);
+        } else if ends_with(path, &["Ntry", "CdtDbtInd"]) {
+            self.credit = Some(value == "CRDT");
+        } else if ends_with(path, &["Ntry", "Sts"]) || ends_with(path, &["Ntry", "Sts", "Cd"]) {
+            self.status = non_empty(value);
+        } else if is_date(path, "BookgDt") {
+            self.booking_date = parse_date(value);
+        } else if is_date(path, "ValDt") {
+            self.value_date = parse_date(value);
+        } else if ends_with(path, &["Ntry", "NtryRef"]) {
+            self.entry_ref = non_empty(value);
+        } else if ends_with(path, &["AcctSvcrRef"]) {
+            if self.servicer_ref.is_none() {
+                self.servicer_ref = non_empty(value);
+            }
+        } else if ends_with(path, &["Refs", "EndToEndId"]) {
+            if self.end_to_end_id.is_none() {
+                self.end_to_end_id = non_empty(value);
+            }
+        } else if ends_with(path, &["Ntry", "AddtlNtryInf"]) {
+            self.additional_info = non_empty(value);
+        } else if ends_with(path, &["Chrgs", "TtlChrgsAndTaxAmt"]) {
+            if self.total_charges.is_none() {
+                self.total_charges = parse_amount(value);
+            }
+        } else if !in_details
+            && (ends_with(path, &["Chrgs", "Rcrd", "Amt"]) || ends_with(path, &["Chrgs", "Amt"]))
+        {
+            if let Some(charge) = parse_amount(value) {
+                self.charge_records = Some(self.charge_records.unwrap_or_default() + charge);
+            }
+        } else if in_details {
+            self.apply_details(path, value);
+        }
+    }
+
+    fn apply_details(&mut self, path: &[String], value: &str) {
+        // Only the first transaction details are used for the counterparty of a batch entry
+        if ends_with(path, &["RltdPties", "Dbtr", "Nm"])
+            || ends_with(path, &["RltdPties", "Dbtr", "Pty", "Nm"])
+        {
+            self.debtor_name = self.debtor_name.take().or_else(|| non_empty(value));
+        } else if ends_with(path, &["RltdPties", "Cdtr", "Nm"])
+            || ends_with(path, &["RltdPties", "Cdtr", "Pty", "Nm"])
+        {
+            self.creditor_name = self.creditor_name.take().or_else(|| non_empty(value));
+        } else if ends_with(path, &["DbtrAcct", "Id", "IBAN"])
+            || ends_with(path, &["DbtrAcct", "Id", "Othr", "Id"])
+        {
+            self.debtor_account = self.debtor_account.take().or_else(|| non_empty(value));
+        } else if ends_with(path, &["CdtrAcct", "Id", "IBAN"])
+            || ends_with(path, &["CdtrAcct", "Id", "Othr", "Id"])
+        {
+            self.creditor_account = self.creditor_account.take().or_else(|| non_empty(value));
+        } else if ends_with(path, &["RmtInf", "Ustrd"])
+            || ends_with(path, &["RmtInf", "Strd", "CdtrRefInf", "Ref"])
+        {
+            if let Some(info) = non_empty(value) {
+                self.remittance.push(info);
+            }
+        } else if ends_with(path, &["TxDtls", "AddtlTxInf"]) && self.additional_info.is_none() {
+            self.additional_info = non_empty(value);
+        }
+    }
+
+    // Entries are numbered from 1 in the diagnostics, as camt has no rows
+    fn store(self, entry_number: usize, statement: &mut Statement) {
+        if matches!(self.status.as_deref(), Some("PDNG") | Some("INFO")) {
+            return;
+        }
+        if self.amount.is_none() {
+            statement.issues.push(RowIssue {
+                row: entry_number,
+                kind: IssueKind::Rejected,
+                reason: "entry has no valid amount".to_owned(),
+            });
+            return;
+        }
+        if self.value_date.or(self.booking_date).is_none() {
+            statement.issues.push(RowIssue {
+                row: entry_number,
+                kind: IssueKind::Incomplete,
+                reason: "entry has no valid booking or value date".to_owned(),
+            });
+        }
+        statement.transactions.push(self.build());
+    }
+
+    fn build(self) -> ExternalTransaction {
+        let credit = self.credit.unwrap_or(false);
+        let amount = self.amount.map(|value| if credit { value } else { -value });
+        // For incoming payments the debtor is the other party, for outgoing the creditor
+        let (other_account, other_account_name) = if credit {
+            (self.debtor_account, self.debtor_name)
+        } else {
+            (self.creditor_account, self.creditor_name)
+        };
+        let description = if self.remittance.is_empty() {
+            None
+        } else {
+            Some(self.remittance.join(" "))
+        };
+        ExternalTransaction {
+            date: self.value_date.or(self.booking_date),
+            booking_date: self.booking_date,
+            amount,
+            category: self.additional_info,
+            description,
+            other_account,
+            other_account_name,
+            textual_date: None,
+            transaction_fee: self
+                .total_charges
+                .or(self.charge_records)
+                .filter(|value| *value > 0.0),
+            reference: self.servicer_ref.or(self.end_to_end_id).or(self.entry_ref),
+            ..Default::default()
+        }
+    }
+}
+
+impl BalanceBuilder {
+    fn apply(&mut self, path: &[String], value: &str) {
+        if ends_with(path, &["Bal", "Tp", "CdOrPrtry", "Cd"]) {
+            self.code = non_empty(value);
+        } else if ends_with(path, &["Bal", "Amt"]) {
+            self.amount = parse_amount(value);
+        } else if ends_with(path, &["Bal", "CdtDbtInd"]) {
+            self.credit = Some(value == "CRDT");
+        } else if is_date(path, "Bal") || ends_with(path, &["Bal", "Dt", "Dt"]) {
+            self.date = parse_date(value);
+        }
+    }
+
+    fn store(self, statement: &mut Statement) {
+        if let Some(amount) = self.amount {
+            let balance = StatementBalance {
+                date: self.date,
+                amount: if self.credit.unwrap_or(true) {
+                    amount
+                } else {
+                    -amount
+                },
+            };
+            match self.code.as_deref() {
+                // Opening booked, or the previously closed booked balance
+                Some("OPBD") | Some("PRCD") if statement.opening_balance.is_none() => {
+                    statement.opening_balance = Some(balance)
+                }
+                Some("CLBD") => statement.closing_balance = Some(balance),
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.02">
+  <BkToCstmrStmt>
+    <Stmt>
+      <Id>2023-01</Id>
+      <Bal>
+        <Tp><CdOrPrtry><Cd>OPBD</Cd></CdOrPrtry></Tp>
+        <Amt Ccy="EUR">1000.00</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+        <Dt><Dt>2023-01-01</Dt></Dt>
+      </Bal>
+      <Bal>
+        <Tp><CdOrPrtry><Cd>CLBD</Cd></CdOrPrtry></Tp>
+        <Amt Ccy="EUR">865.50</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+        <Dt><Dt>2023-01-31</Dt></Dt>
+      </Bal>
+      <Ntry>
+        <Amt Ccy="EUR">135.50</Amt>
+        <CdtDbtInd>DBIT</CdtDbtInd>
+        <Sts>BOOK</Sts>
+        <BookgDt><Dt>2023-01-11</Dt></BookgDt>
+        <ValDt><Dt>2023-01-10</Dt></ValDt>
+        <AcctSvcrRef>REF-123</AcctSvcrRef>
+        <Chrgs><TtlChrgsAndTaxAmt Ccy="EUR">0.50</TtlChrgsAndTaxAmt></Chrgs>
+        <NtryDtls>
+          <TxDtls>
+            <Refs><EndToEndId>E2E-1</EndToEndId></Refs>
+            <RltdPties>
+              <Cdtr><Nm>Landlord &amp; Co</Nm></Cdtr>
+              <CdtrAcct><Id><IBAN>AT611904300234573201</IBAN></Id></CdtrAcct>
+            </RltdPties>
+            <RmtInf><Ustrd>Rent January</Ustrd></RmtInf>
+          </TxDtls>
+        </NtryDtls>
+        <AddtlNtryInf>SEPA Credit Transfer</AddtlNtryInf>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="EUR">1.00</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+        <Sts>PDNG</Sts>
+        <BookgDt><Dt>2023-01-31</Dt></BookgDt>
+      </Ntry>
+    </Stmt>
+  </BkToCstmrStmt>
+</Document>"#;
+
+    #[test]
+    fn test_parse_camt053() {
+        let statement = CamtFormat {}.parse_statement(SAMPLE).unwrap();
+        assert_eq!(statement.transactions.len(), 1);
+        let entry = &statement.transactions[0];
+        assert_eq!(entry.date, NaiveDate::from_ymd_opt(2023, 1, 10));
+        assert_eq!(entry.booking_date, NaiveDate::from_ymd_opt(2023, 1, 11));
+        assert_eq!(entry.amount, Some(-135.5));
+        assert_eq!(entry.transaction_fee, Some(0.5));
+        assert_eq!(entry.description.as_deref(), Some("Rent January"));
+        assert_eq!(entry.category.as_deref(), Some("SEPA Credit Transfer"));
+        assert_eq!(entry.other_account.as_deref(), Some("AT611904300234573201"));
+        assert_eq!(entry.other_account_name.as_deref(), Some("Landlord & Co"));
+        assert_eq!(entry.reference.as_deref(), Some("REF-123"));
+
+        let opening = statement.opening_balance.unwrap();
+        assert_eq!(opening.amount, 1000.0);
+        assert_eq!(opening.date, NaiveDate::from_ymd_opt(2023, 1, 1));
+        assert_eq!(statement.closing_balance.unwrap().amount, 865.5);
+    }
+}
@@ -1,10 +1,24 @@
 use clap::{Parser, Subcommand};
 use clap_complete::Shell;
 
+use crate::external_models::DateKind;
+use crate::normalize::NormalizeStep;
+use crate::query::text_match::MatchMode;
+use crate::query::transactions::{ReconcileState, SortKey};
+use crate::reports::series::{Interval, OutputFormat, SeriesValue};
+
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 #[command(propagate_version = true)]
 pub(crate) struct Cli {
+    // How the name, description, memo and guid filters match the values
+    #[arg(long = "match", value_enum, global = true, default_value = "contains")]
+    pub match_mode: MatchMode,
+
+    // Compare the text filters case sensitively
+    #[arg(long = "case-sensitive", global = true)]
+    pub case_sensitive: bool,
+
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -12,9 +26,15 @@ pub(crate) struct Cli {
 #[derive(Subcommand)]
 pub(crate) enum Commands {
     ListAccounts(ListAccountsArgs),
-    Transactions(TransactionsArgs),
-    Correlate(CorrelateArgs),
+    Transactions(Box<TransactionsArgs>),
+    Correlate(Box<CorrelateArgs>),
     Commodities(CommoditiesArgs),
+    Balance(BalanceArgs),
+    Budget(BudgetArgs),
+    Report {
+        #[command(subcommand)]
+        report: ReportCommands,
+    },
     Completions {
         #[arg(value_enum)]
         shell: Shell,
@@ -27,6 +47,14 @@ pub struct ListAccountsArgs {
     #[arg(long = "limit", short = 'l')]
     pub limit: Option<i64>,
 
+    // Show the accounts as a tree with full names, under the matching accounts, if there is a filter
+    #[arg(long = "tree")]
+    pub tree: bool,
+
+    // Show the tree only down to the given level, 1 is the top level accounts
+    #[arg(long = "depth", requires = "tree")]
+    pub depth: Option<usize>,
+
     #[command(flatten)]
     pub account: DefaultAccountParams,
 }
@@ -57,10 +85,66 @@ pub struct TransactionsArgs {
     #[arg(long = "description", short = 'd')]
     pub description: Option<String>,
 
+    // Splits with at least the given value or quantity, like -100.50
+    #[arg(long = "min-amount", allow_hyphen_values = true)]
+    pub min_amount: Option<String>,
+
+    // Splits with at most the given value or quantity
+    #[arg(long = "max-amount", allow_hyphen_values = true)]
+    pub max_amount: Option<String>,
+
+    // Splits with exactly the given value or quantity
+    #[arg(long = "amount", allow_hyphen_values = true)]
+    pub amount: Option<String>,
+
+    // Order of the splits
+    #[arg(long = "sort", value_enum, default_value = "date")]
+    pub sort: SortKey,
+
+    // Sort in descending order
+    #[arg(long = "desc")]
+    pub descending: bool,
+
+    // Skip the given number of splits, for paging with --limit
+    #[arg(long = "offset")]
+    pub offset: Option<i64>,
+
+    // Splits in the given reconcile states: n(ew), c(leared), y (reconciled), f(rozen) or v(oid)
+    #[arg(long = "reconcile-state", value_enum, value_delimiter = ',')]
+    pub reconcile_states: Vec<ReconcileState>,
+
+    // Splits reconciled on or after the given day, in yyyy-mm-dd format
+    #[arg(long = "reconciled-after")]
+    pub reconciled_after: Option<String>,
+
+    // Splits reconciled on or before the given day, in yyyy-mm-dd format
+    #[arg(long = "reconciled-before")]
+    pub reconciled_before: Option<String>,
+
+    // List the splits of the sub-accounts of the matching accounts too
+    #[arg(long = "include-children", short = 'c')]
+    pub include_children: bool,
+
+    // Show the balance of the account after each split, and the cleared balance, needs one account
+    #[arg(
+        long = "running-balance",
+        short = 'a',
+        conflicts_with_all = ["move_split", "group_by_transaction"]
+    )]
+    pub running_balance: bool,
+
     // Move the found splits to the target account
     #[arg(long = "move-split", short = 'm')]
     pub move_split: bool,
 
+    // Show the transactions of the found splits, with every split and account name
+    #[arg(
+        long = "group-by-transaction",
+        short = 'w',
+        conflicts_with = "move_split"
+    )]
+    pub group_by_transaction: bool,
+
     #[command(flatten)]
     pub account: DefaultAccountParams,
     #[command(flatten)]
@@ -69,26 +153,75 @@ pub struct TransactionsArgs {
 
 #[derive(Args)]
 pub struct CorrelateArgs {
-    // The file which contains a list of transaction to correlate
-    #[arg(long = "input", short = 'i')]
-    pub input: String,
+    // The files, or glob patterns, which contain a list of transaction to correlate
+    #[arg(long = "input", short = 'i', required = true, num_args = 1..)]
+    pub input: Vec<String>,
 
     // The name of the sheet
     #[arg(long = "sheet-name", short = 's')]
     pub sheet_name: Option<String>,
 
+    // Read every sheet of the input files, not just the first one
+    #[arg(long = "all-sheets", short = 'A', conflicts_with = "sheet_name")]
+    pub all_sheets: bool,
+
     // The format of the sheet
     #[arg(long = "format", short = 'f')]
     pub format: Option<String>,
 
-    // Match transactions by the booking date
-    #[arg(long = "by-booking-date", short = 'd')]
+    // Match transactions by the booking date, the same as --match-dates booking,value
+    #[arg(long = "by-booking-date", short = 'd', conflicts_with = "match_dates")]
     pub by_booking_date: bool,
 
+    // Statement dates used for matching, in the order of preference, like textual,value,booking
+    #[arg(long = "match-dates", value_enum, value_delimiter = ',')]
+    pub match_dates: Option<Vec<DateKind>>,
+
+    // Statement dates used as the post date of the added transactions, in the order of preference
+    #[arg(long = "post-dates", value_enum, value_delimiter = ',')]
+    pub post_dates: Option<Vec<DateKind>>,
+
     // List extra transactions not found in the external source
     #[arg(long = "list-extra-transactions", short = 'X')]
     pub list_extra_transactions: bool,
 
+    // Date pattern of the text cells, like %d/%m/%Y, can be repeated
+    #[arg(long = "date-format")]
+    pub date_formats: Vec<String>,
+
+    // Spending date pattern of the descriptions: ymd-dotted, dmy-dotted, dm-dotted-time, dmy-slash,
+    // iso-timestamp, or a regex with (?P<d>..), (?P<m>..) and optional (?P<y>..) groups, can be repeated
+    #[arg(long = "textual-date-pattern")]
+    pub textual_date_patterns: Vec<String>,
+
+    // Decimal separator of the amounts written as text
+    #[arg(long = "decimal-separator")]
+    pub decimal_separator: Option<char>,
+
+    // Normalization steps of the descriptions and names, replacing the defaults of the format
+    #[arg(long = "normalize", value_enum, value_delimiter = ',')]
+    pub normalize: Option<Vec<NormalizeStep>>,
+
+    // Normalization steps of only the counterparty names, applied after --normalize
+    #[arg(long = "normalize-names", value_enum, value_delimiter = ',')]
+    pub normalize_names: Option<Vec<NormalizeStep>>,
+
+    // Regular expression to remove from the descriptions, like '\*{4}\d{4}', can be repeated
+    #[arg(long = "remove-pattern")]
+    pub remove_patterns: Vec<String>,
+
+    // Counterparty alias in PATTERN=NAME form, applied when the regex matches, can be repeated
+    #[arg(long = "payee-alias")]
+    pub payee_aliases: Vec<String>,
+
+    // Store the exchange rate of foreign currency payments in the price database
+    #[arg(long = "store-prices")]
+    pub store_prices: bool,
+
+    // Abort before matching, if any row of the input is malformed
+    #[arg(long = "strict")]
+    pub strict: bool,
+
     // Verbose logging
     #[arg(long = "verbose", short = 'v')]
     pub verbose: bool,
@@ -103,6 +236,136 @@ pub struct CorrelateArgs {
     pub fee_account: FeeAccountParams,
 }
 
+#[derive(Args)]
+pub struct BalanceArgs {
+    // Balance at the end of the given day in yyyy-mm-dd format, every split is counted by default
+    #[arg(long = "date", short = 'd')]
+    pub date: Option<String>,
+
+    // Include the balances of the child accounts in their parents
+    #[arg(long = "rollup")]
+    pub rollup: bool,
+
+    // Show the stored signs, instead of showing incomes, liabilities and equity as positive
+    #[arg(long = "raw-sign")]
+    pub raw_sign: bool,
+
+    #[command(flatten)]
+    pub account: DefaultAccountParams,
+}
+
+#[derive(Subcommand)]
+pub(crate) enum ReportCommands {
+    Income(IncomeReportArgs),
+    BalanceSheet(BalanceSheetArgs),
+    Series(SeriesArgs),
+    Reconciliation(ReconciliationArgs),
+}
+
+#[derive(Args)]
+pub struct IncomeReportArgs {
+    // First day of the period in yyyy-mm-dd format, the start of the year by default
+    #[arg(long = "from", short = 'f')]
+    pub from: Option<String>,
+
+    // Last day of the period in yyyy-mm-dd format, today by default
+    #[arg(long = "to", short = 't')]
+    pub to: Option<String>,
+
+    // Compare with the previous period of the same length
+    #[arg(long = "compare", short = 'c')]
+    pub compare: bool,
+
+    // Break down the period by months
+    #[arg(long = "monthly", short = 'm')]
+    pub monthly: bool,
+
+    // Show the accounts only down to the given level, the totals still include everything
+    #[arg(long = "depth")]
+    pub depth: Option<usize>,
+}
+
+#[derive(Args)]
+pub struct BalanceSheetArgs {
+    // Balances at the end of the given day in yyyy-mm-dd format, today by default
+    #[arg(long = "date", short = 'd')]
+    pub date: Option<String>,
+
+    // The currency of the report, like EUR
+    #[arg(long = "currency", short = 'c', required = true)]
+    pub currency: String,
+
+    // Show the accounts only down to the given level, the totals still include everything
+    #[arg(long = "depth")]
+    pub depth: Option<usize>,
+}
+
+#[derive(Args)]
+pub struct SeriesArgs {
+    // First day of the series in yyyy-mm-dd format, the start of the year by default
+    #[arg(long = "from", short = 'f')]
+    pub from: Option<String>,
+
+    // Last day of the series in yyyy-mm-dd format, today by default
+    #[arg(long = "to")]
+    pub to: Option<String>,
+
+    // Length of a row
+    #[arg(long = "interval", short = 'i', value_enum, default_value = "month")]
+    pub interval: Interval,
+
+    // The net flow within the period, or the balance at its end
+    #[arg(long = "value", value_enum, default_value = "flow")]
+    pub value: SeriesValue,
+
+    #[arg(long = "output", short = 'o', value_enum, default_value = "csv")]
+    pub output: OutputFormat,
+
+    // Include the child accounts in the same commodity, always done without account filter
+    #[arg(long = "rollup")]
+    pub rollup: bool,
+
+    // Limit number of accounts, the top level accounts are used without account filter
+    #[arg(long = "limit", short = 'l')]
+    pub limit: Option<i64>,
+
+    #[command(flatten)]
+    pub account: DefaultAccountParams,
+}
+
+#[derive(Args)]
+pub struct ReconciliationArgs {
+    // Show the state at the given day in yyyy-mm-dd format, today by default
+    #[arg(long = "date", short = 'd')]
+    pub date: Option<String>,
+
+    // Count the splits which are not cleared for more than this many days
+    #[arg(long = "older-than", short = 'o', default_value_t = 30)]
+    pub older_than: i64,
+
+    #[command(flatten)]
+    pub account: DefaultAccountParams,
+}
+
+#[derive(Args)]
+pub struct BudgetArgs {
+    // Name of the budget, needed only if the book has more than one
+    #[arg(long = "budget-name", short = 'b')]
+    pub name: Option<String>,
+
+    // Show the period containing the given day in yyyy-mm-dd format, today by default
+    #[arg(long = "date", short = 'd')]
+    pub date: Option<String>,
+
+    // Show the given period of the budget, starting from 1
+    #[arg(long = "period", short = 'p', conflicts_with = "date")]
+    pub period: Option<i32>,
+
+    // Show every period of the budget
+    #[arg(long = "all-periods", short = 'a', conflicts_with_all = ["date", "period"])]
+    pub all_periods: bool,
+}
+
 #[derive(Args)]
 pub struct CommoditiesArgs {
     // List only a given type of commodities
@@ -131,6 +394,9 @@ pub struct DefaultAccountParams {
 
     #[arg(long = "account-parent-name")]
     pub parent_name: Option<String>,
+    // Exact full name of the account, like Expenses:Food:Groceries
+    #[arg(long = "account-path")]
+    pub path: Option<String>,
 }
 
 #[derive(Args)]
@@ -145,6 +411,9 @@ pub struct TargetAccountParams {
     pub target_account_type: Option<String>,
     #[arg(long = "target-parent-name")]
     pub target_parent_name: Option<String>,
+    // Exact full name of the account, like Expenses:Food:Groceries
+    #[arg(long = "target-account-path")]
+    pub target_path: Option<String>,
 }
 
 #[derive(Args)]
@@ -159,6 +428,9 @@ pub struct FeeAccountParams {
     pub fee_account_type: Option<String>,
     #[arg(long = "fee-parent-name")]
     pub fee_parent_name: Option<String>,
+    // Exact full name of the account, like Expenses:Food:Groceries
+    #[arg(long = "fee-account-path")]
+    pub fee_path: Option<String>,
 }
 
 #[derive(Args)]
@@ -173,4 +445,7 @@ pub struct FromAccountParams {
     pub from_account_type: Option<String>,
     #[arg(long = "from-parent-name")]
     pub from_parent_name: Option<String>,
+    // Exact full name of the account, like Expenses:Food:Groceries
+    #[arg(long = "from-account-path")]
+    pub from_path: Option<String>,
 }
@@ -1,29 +1,36 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::ops::Bound::Included;
 
 use anyhow::Result;
-use chrono::{Duration, Local, NaiveDate};
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime};
 use console::{style, Key, Term};
 use diesel::prelude::*;
 use guid_create::GUID;
 
-use crate::dbmodifier::{NewSplit, NewTransaction};
+use crate::dbmodifier::{NewPrice, NewSlot, NewSplit, NewTransaction};
 use crate::external_models::{
-    ExternalTransaction, ExternalTransactionList, Matching, SheetDefinition, SheetFormat,
+    DateOrder, ExternalTransaction, ExternalTransactionList, InputFormat, InputSource,
     TransactionPairing,
 };
-use crate::models::{Account, Split, Transaction};
+use crate::models::{Account, Commodities, Split, Transaction};
 use crate::query::accounts::AccountQuery;
 use crate::query::currencies::CommoditiesQuery;
+use crate::query::indexes::missing_indexes;
 use crate::query::transactions::TransactionQuery;
 use crate::utils::{format_guid, get_value_or_empty, to_string};
 
+// The largest difference in days between the statement and the book, which is still matched
+const MAX_DELTA_DAYS: i64 = 10;
+
 pub struct CorrelationCommand {
-    pub input_file: String,
-    pub sheet_name: Option<String>,
-    pub matching: Matching,
+    pub input: InputSource,
+    // Overrides the dates of the format used for matching
+    pub match_dates: Option<DateOrder>,
+    // Overrides the dates of the format used as the post date of the added transactions
+    pub post_dates: Option<DateOrder>,
     pub verbose: bool,
     pub list_extra_transactions: bool,
+    pub store_prices: bool,
     pub account_query: AccountQuery,
     pub counterparty_account_query: AccountQuery,
     pub fee_account_query: AccountQuery,
@@ -32,44 +39,40 @@ pub struct CorrelationCommand {
 struct TransactionCorrelator {
     external_transactions: ExternalTransactionList,
     account: String,
-    matching: Matching,
+    matching: DateOrder,
     transaction_map: BTreeMap<NaiveDate, Vec<TransactionPairing>>,
+    // bank transaction id -> split guid of the already imported transactions
+    online_ids: HashMap<String, String>,
     verbose: bool,
 }
 
 impl TransactionCorrelator {
     pub fn new(
-        input_file: &str,
-        sheet_name: &Option<String>,
+        input: &InputSource,
         account: String,
-        matching: Matching,
+        matching: DateOrder,
         verbose: bool,
-        format: &Box<dyn SheetFormat>,
+        format: &InputFormat,
         term: &Term,
     ) -> Result<Self> {
-        let mut sheet_definition = SheetDefinition::new(input_file)?;
-
-        let external_transactions = sheet_definition.load(sheet_name, matching, format, term)?;
+        let external_transactions = ExternalTransactionList::load(input, &matching, format, term)?;
         Ok(TransactionCorrelator {
             external_transactions,
             account,
             matching,
             transaction_map: BTreeMap::new(),
+            online_ids: HashMap::new(),
             verbose,
         })
     }
 
+    // Only the splits which could be paired with the statement rows, considering the tolerance of the matching
     fn load_from_database(&self, connection: &mut SqliteConnection) -> Vec<(Split, Transaction)> {
-        let db_query = TransactionQuery {
-            limit: 10000,
-            txid_filter: None,
-            account_filter: Some(self.account.clone()),
-            description_filter: None,
-            memo_filter: None,
-            before_filter: None,
-            after_filter: None,
-        };
-        let db_rows = db_query.execute(connection);
+        let tolerance = Duration::days(MAX_DELTA_DAYS);
+        let after = self.get_min_date().map(|date| date - tolerance);
+        let before = self.get_max_date().map(|date| date + tolerance);
+        let db_rows =
+            TransactionQuery::load_account_splits(connection, &self.account, after, before);
         if self.verbose {
             println!("Number of transactions in the database: {}", db_rows.len());
         }
@@ -77,14 +80,32 @@ impl TransactionCorrelator {
     }
 
     fn get_min_date(&self) -> Option<NaiveDate> {
-        self.external_transactions.1.to_owned()
+        self.external_transactions.min_date.to_owned()
     }
 
     fn get_max_date(&self) -> Option<NaiveDate> {
-        self.external_transactions.2.to_owned()
+        self.external_transactions.max_date.to_owned()
+    }
+
+    fn display_balances(&self, term: &Term) -> Result<()> {
+        if let Some(opening) = &self.external_transactions.opening_balance {
+            term.write_line(&format!("Opening balance: {}", style(opening).cyan()))?;
+        }
+        if let Some(closing) = &self.external_transactions.closing_balance {
+            term.write_line(&format!("Closing balance: {}", style(closing).cyan()))?;
+        }
+        Ok(())
     }
 
     fn build_mapping(&mut self, connection: &mut SqliteConnection) {
+        let missing = missing_indexes(connection);
+        if !missing.is_empty() {
+            println!(
+                "{} the book has no index on {}, loading could be slow",
+                style("Warning:").yellow(),
+                missing.join(", ")
+            );
+        }
         let db_transactions = self.load_from_database(connection);
 
         for row in db_transactions {
@@ -93,8 +114,10 @@ impl TransactionCorrelator {
                 list.push(TransactionPairing::new(row));
             }
         }
+        self.online_ids = TransactionQuery::load_online_ids(connection, &self.account);
         if self.verbose {
             println!("Found {} separate date", self.transaction_map.len());
+            println!("Found {} bank reference", self.online_ids.len());
         }
     }
 
@@ -119,10 +142,17 @@ impl TransactionCorrelator {
     }
 
     pub fn match_transactions(&mut self) -> Vec<ExternalTransaction> {
-        let mut working_set = self.external_transactions.0.clone();
+        let mut working_set = self.external_transactions.transactions.clone();
         if self.verbose {
             println!("Starting with {} transactions", &working_set.len());
         }
+        working_set = self.match_by_reference(&working_set);
+        if self.verbose {
+            println!(
+                "After matching by bank reference, {} transaction remained as unmatched",
+                &working_set.len()
+            );
+        }
         working_set = self.match_transactions_with_delta_day(0, &working_set);
         if self.verbose {
             println!(
@@ -131,7 +161,7 @@ impl TransactionCorrelator {
             );
         }
         let mut delta_day = 0;
-        while !&working_set.is_empty() && delta_day < 10 {
+        while !&working_set.is_empty() && delta_day < MAX_DELTA_DAYS {
             delta_day += 1;
             working_set = self.match_transactions_with_delta_day(delta_day, &working_set);
             working_set = self.match_transactions_with_delta_day(-delta_day, &working_set);
@@ -146,6 +176,32 @@ impl TransactionCorrelator {
         working_set
     }
 
+    // A row is already imported, if its bank reference is stored in the book,
+    // regardless of its date or amount. Returns the unmatched transactions.
+    fn match_by_reference(&self, transactions: &[ExternalTransaction]) -> Vec<ExternalTransaction> {
+        let mut result = Vec::new();
+        for external_transaction in transactions {
+            let split_guid = external_transaction
+                .reference
+                .as_ref()
+                .and_then(|reference| self.online_ids.get(reference));
+            match split_guid {
+                Some(guid) => {
+                    if let Some(pairing) = self
+                        .transaction_map
+                        .values()
+                        .flatten()
+                        .find(|pairing| pairing.is_split(guid) && pairing.is_not_matched())
+                    {
+                        pairing.pair_with(external_transaction);
+                    }
+                }
+                None => result.push(external_transaction.clone()),
+            }
+        }
+        result
+    }
+
     // return the unmatched transactions
     pub fn match_transactions_with_delta_day(
         &self,
@@ -169,7 +225,7 @@ impl TransactionCorrelator {
         delta_day: i64,
         external_transaction: &ExternalTransaction,
     ) -> Option<&TransactionPairing> {
-        if let Some(ext_date) = external_transaction.get_matching_date(self.matching) {
+        if let Some(ext_date) = external_transaction.get_date_by(&self.matching) {
             let actual_date = match delta_day {
                 0 => ext_date,
                 _ => ext_date
@@ -206,6 +262,8 @@ struct AddTransactions<'a> {
     only_account: &'a Account,
     counter_account: &'a Account,
     fee_account: &'a Option<Account>,
+    store_prices: bool,
+    post_dates: &'a DateOrder,
     term: &'a Term,
 }
 
@@ -214,14 +272,27 @@ impl CorrelationCommand {
         &mut self,
         connection: &mut SqliteConnection,
         term: &Term,
-        format: &Box<dyn SheetFormat>,
+        format: &InputFormat,
     ) -> Result<usize> {
-        if let Some(only_account) = self.account_query.get_one(connection, true) {
+        let match_dates = self
+            .match_dates
+            .clone()
+            .unwrap_or_else(|| format.date_order());
+        let post_dates = self
+            .post_dates
+            .clone()
+            .unwrap_or_else(|| format.date_order());
+        if self.verbose {
+            println!(
+                "Matching by {} dates, posting by {} dates",
+                match_dates, post_dates
+            );
+        }
+        if let Some(only_account) = self.account_query.get_one(connection, true)? {
             let mut correlator = TransactionCorrelator::new(
-                &self.input_file.clone(),
-                &self.sheet_name,
+                &self.input,
                 only_account.guid.clone(),
-                self.matching,
+                match_dates,
                 self.verbose,
                 format,
                 term,
@@ -233,6 +304,7 @@ impl CorrelationCommand {
                 style(to_string(correlator.get_min_date())).cyan(),
                 style(to_string(correlator.get_max_date())).cyan()
             ))?;
+            correlator.display_balances(term)?;
 
             let unmatched_transactions = correlator.match_transactions();
             term.write_line(&format!(
@@ -259,9 +331,9 @@ impl CorrelationCommand {
             }
 
             if !unmatched_transactions.is_empty() {
-                let fee_account = self.fee_account_query.get_one(connection, false);
+                let fee_account = self.fee_account_query.get_one(connection, false)?;
                 if let Some(counter_account) =
-                    self.counterparty_account_query.get_one(connection, true)
+                    self.counterparty_account_query.get_one(connection, true)?
                 {
                     let mut add_transactions = AddTransactions {
                         connection,
@@ -269,6 +341,8 @@ impl CorrelationCommand {
                         only_account: &only_account,
                         counter_account: &counter_account,
                         fee_account: &fee_account,
+                        store_prices: self.store_prices,
+                        post_dates: &post_dates,
                         term,
                     };
                     add_transactions.try_to_fix()?;
@@ -366,13 +440,18 @@ impl<'a> AddTransactions<'a> {
             .expect("Currency not found!");
         let tr_guid = format_guid(&GUID::rand().to_string());
         let spend_date = transaction
-            .get_matching_date(Matching::BySpending)
+            .get_date_by(self.post_dates)
             .map(|d| d.and_hms_opt(12, 0, 0).expect("Correct date"));
         let current_time = Local::now().naive_local();
         let description = transaction
             .get_description_or_category()
             .unwrap_or_else(|| "".to_owned());
         let amount = transaction.get_amount().expect("Amount is expected!");
+        let memo = match transaction.get_foreign_amount_desc() {
+            Some(foreign) if description.is_empty() => foreign,
+            Some(foreign) => format!("{} ({})", description, foreign),
+            None => description.clone(),
+        };
 
         let fee_value = &transaction.transaction_fee.unwrap_or_default();
 
@@ -384,14 +463,17 @@ impl<'a> AddTransactions<'a> {
             current_time,
             &description,
         );
-        let _split_id_from = NewSplit::insert(
+        let split_id_from = NewSplit::insert(
             self.connection,
             &tr_guid,
             self.only_account,
-            &description,
+            &memo,
             &commodity,
             amount,
         );
+        if let Some(reference) = &transaction.reference {
+            NewSlot::insert_online_id(self.connection, &split_id_from, reference);
+        }
         let _split_id_counter = NewSplit::insert(
             self.connection,
             &tr_guid,
@@ -410,6 +492,11 @@ impl<'a> AddTransactions<'a> {
                 *fee_value,
             );
         }
+        if self.store_prices {
+            if let Some(post_date) = spend_date {
+                self.store_exchange_rate(transaction, &commodity, post_date)?;
+            }
+        }
         /*        self.term.write_line(&format!(
             "trans id:{} \n\t{} - {} \n\t{} - {}",
             tr_guid,
@@ -422,6 +509,33 @@ impl<'a> AddTransactions<'a> {
     }
 }
 
+impl<'a> AddTransactions<'a> {
+    fn store_exchange_rate(
+        &mut self,
+        transaction: &ExternalTransaction,
+        currency: &Commodities,
+        date: NaiveDateTime,
+    ) -> Result<()> {
+        let (foreign_code, rate) = match (
+            &transaction.original_currency,
+            transaction.get_exchange_rate(),
+        ) {
+            (Some(code), Some(rate)) if *code != currency.mnemonic => (code, rate),
+            _ => return Ok(()),
+        };
+        match CommoditiesQuery::get_currency(self.connection, foreign_code) {
+            Some(foreign) => {
+                NewPrice::insert(self.connection, &foreign.guid, &currency.guid, date, rate);
+            }
+            None => self.term.write_line(&format!(
+                "Currency {} is not in the book, the exchange rate is not stored",
+                style(foreign_code).red()
+            ))?,
+        }
+        Ok(())
+    }
+}
+
 impl Answer {
     fn get(term: &Term) -> Result<Answer> {
         loop {
@@ -442,3 +556,43 @@ impl Answer {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::test_book;
+
+    #[test]
+    fn test_match_by_reference() {
+        let connection = &mut test_book::empty_book();
+        test_book::add_split(connection, 1, "bank", "2023-01-20", -1000, -1000);
+        test_book::add_online_id(connection, 1, "REF-1");
+        // the amount differs from the book, only the reference pairs them
+        let entry = |reference: &str| ExternalTransaction {
+            date: NaiveDate::from_ymd_opt(2023, 1, 20),
+            amount: Some(-55.0),
+            reference: Some(reference.to_owned()),
+            ..Default::default()
+        };
+        let matching = DateOrder::default();
+        let mut correlator = TransactionCorrelator {
+            external_transactions: ExternalTransactionList::new(
+                vec![entry("REF-1"), entry("REF-2")],
+                &matching,
+            ),
+            account: "bank".to_owned(),
+            matching,
+            transaction_map: BTreeMap::new(),
+            online_ids: HashMap::new(),
+            verbose: false,
+        };
+        correlator.build_mapping(connection);
+        assert_eq!(correlator.get_unmatched().len(), 1);
+
+        let unmatched =
+            correlator.match_by_reference(&correlator.external_transactions.transactions);
+        assert_eq!(unmatched.len(), 1);
+        assert_eq!(unmatched[0].reference.as_deref(), Some("REF-2"));
+        assert!(correlator.get_unmatched().is_empty());
+    }
+}
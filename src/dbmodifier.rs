@@ -3,7 +3,7 @@ use diesel::prelude::*;
 use guid_create::GUID;
 
 use crate::models::{Account, Commodities};
-use crate::schema::{splits, transactions};
+use crate::schema::{prices, slots, splits, transactions};
 use crate::utils::{format_guid, format_sqlite_date, DenominatedValue};
 
 #[derive(Insertable, Debug)]
@@ -35,6 +35,35 @@ pub struct NewTransaction<'a> {
     pub description: &'a str,
 }
 
+#[derive(Insertable, Debug)]
+#[diesel(table_name = prices)]
+pub struct NewPrice<'a> {
+    pub guid: &'a str,
+    pub commodity_guid: &'a str,
+    pub currency_guid: &'a str,
+    pub date: &'a str,
+    pub source: &'a str,
+    pub type_: &'a str,
+    pub value_num: i64,
+    pub value_denom: i64,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = slots)]
+pub struct NewSlot<'a> {
+    pub obj_guid: &'a str,
+    pub name: &'a str,
+    pub slot_type: i32,
+    pub string_val: &'a str,
+}
+
+// The slot name and type GnuCash's own importers use for the bank's transaction id
+pub const ONLINE_ID_SLOT: &str = "online_id";
+const SLOT_TYPE_STRING: i32 = 4;
+
+// Exchange rates are stored with 6 decimal digits, like the GnuCash transfer dialog does
+const PRICE_DENOM: i32 = 1_000_000;
+
 impl<'a> NewSplit<'a> {
     fn new_with_defaults(
         guid: &'a str,
@@ -142,3 +171,54 @@ impl<'a> NewTransaction<'a> {
         inserted_rows
     }
 }
+
+impl<'a> NewPrice<'a> {
+    pub fn insert(
+        connection: &mut SqliteConnection,
+        commodity_guid: &'a str,
+        currency_guid: &'a str,
+        date: NaiveDateTime,
+        rate: f64,
+    ) -> String {
+        let price_guid = format_guid(&GUID::rand().to_string());
+        let formatted_date = format_sqlite_date(&date);
+        let value = DenominatedValue::denominate_float(rate, PRICE_DENOM);
+        let price = NewPrice {
+            guid: &price_guid,
+            commodity_guid,
+            currency_guid,
+            date: &formatted_date,
+            source: "user:xfer-dialog",
+            type_: "transaction",
+            value_num: value.value,
+            value_denom: value.denom,
+        };
+        let inserted_rows = diesel::insert_into(prices::table)
+            .values(&price)
+            .execute(connection)
+            .expect("Error saving price");
+        assert_eq!(1, inserted_rows);
+        price_guid
+    }
+}
+
+impl<'a> NewSlot<'a> {
+    pub fn insert_online_id(
+        connection: &mut SqliteConnection,
+        split_guid: &'a str,
+        online_id: &'a str,
+    ) -> usize {
+        let slot = NewSlot {
+            obj_guid: split_guid,
+            name: ONLINE_ID_SLOT,
+            slot_type: SLOT_TYPE_STRING,
+            string_val: online_id,
+        };
+        let inserted_rows = diesel::insert_into(slots::table)
+            .values(&slot)
+            .execute(connection)
+            .expect("Error saving slot");
+        assert_eq!(1, inserted_rows);
+        inserted_rows
+    }
+}
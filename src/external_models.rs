@@ -1,15 +1,20 @@
+use std::collections::HashMap;
 use std::fmt;
-use std::fs::File;
+use std::fs::{self, File};
 use std::{cell::RefCell, io::BufReader};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use calamine::{open_workbook_auto, DataType, Range, Reader, Sheets};
 use chrono::NaiveDate;
+use clap::ValueEnum;
 use console::{style, Term};
 
 use crate::models::{Split, Transaction};
+use crate::normalize::{Normalizer, NormalizerOverrides};
+use crate::sheets::CellConverter;
+use crate::utils::{DateExtractor, DMY_DOTTED, ISO_TIMESTAMP, YMD_DOTTED};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct ExternalTransaction {
     pub date: Option<NaiveDate>,
     pub booking_date: Option<NaiveDate>,
@@ -20,6 +25,10 @@ pub struct ExternalTransaction {
     pub other_account_name: Option<String>,
     pub textual_date: Option<NaiveDate>,
     pub transaction_fee: Option<f64>,
+    pub reference: Option<String>,
+    pub original_currency: Option<String>,
+    pub original_amount: Option<f64>,
+    pub exchange_rate: Option<f64>,
 }
 
 impl fmt::Display for ExternalTransaction {
@@ -37,6 +46,9 @@ impl fmt::Display for ExternalTransaction {
         if let Some(amount) = self.amount {
             write!(f, " {}", amount)?;
         }
+        if let Some(foreign) = self.get_foreign_amount_desc() {
+            write!(f, " ({})", foreign)?;
+        }
         if let Some(transaction_fee) = self.transaction_fee {
             write!(f, " (fee: {})", transaction_fee)?;
         }
@@ -46,25 +58,61 @@ impl fmt::Display for ExternalTransaction {
         if let Some(description) = &self.description {
             write!(f, " - {}", description)?;
         }
+        if let Some(reference) = &self.reference {
+            write!(f, " <{}>", reference)?;
+        }
         Ok(())
     }
 }
 
-#[derive(Copy, Clone)]
-pub enum Matching {
-    ByBooking,
-    BySpending,
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum DateKind {
+    // The value date of the statement row
+    Value,
+    // The date when the bank booked the transaction
+    Booking,
+    // The real spending date, found in the description
+    Textual,
+}
+
+// The dates of a statement row in the order of preference, the first one present is used.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DateOrder(pub Vec<DateKind>);
+
+impl Default for DateOrder {
+    fn default() -> Self {
+        DateOrder(vec![DateKind::Textual, DateKind::Value, DateKind::Booking])
+    }
+}
+
+impl fmt::Display for DateOrder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<_> = self
+            .0
+            .iter()
+            .map(|kind| match kind {
+                DateKind::Value => "value",
+                DateKind::Booking => "booking",
+                DateKind::Textual => "textual",
+            })
+            .collect();
+        f.write_str(&names.join(","))
+    }
 }
 
 impl ExternalTransaction {
-    // TODO: make it configurable
-    pub fn get_matching_date(&self, matching: Matching) -> Option<NaiveDate> {
-        match matching {
-            Matching::ByBooking => self.date,
-            Matching::BySpending => self.textual_date.or(self.date),
+    pub fn get_date(&self, kind: DateKind) -> Option<NaiveDate> {
+        match kind {
+            DateKind::Value => self.date,
+            DateKind::Booking => self.booking_date,
+            DateKind::Textual => self.textual_date,
         }
     }
 
+    pub fn get_date_by(&self, order: &DateOrder) -> Option<NaiveDate> {
+        order.0.iter().find_map(|kind| self.get_date(*kind))
+    }
+
     pub fn get_description_or_category(&self) -> Option<String> {
         self.description.clone().or_else(|| self.category.clone())
     }
@@ -77,6 +125,37 @@ impl ExternalTransaction {
         self.amount
     }
 
+    // The price of one unit of the original currency in the account's currency
+    pub fn get_exchange_rate(&self) -> Option<f64> {
+        match (self.amount, self.original_amount) {
+            (Some(amount), Some(original)) if original != 0.0 => Some((amount / original).abs()),
+            _ => self.exchange_rate,
+        }
+    }
+
+    pub fn get_foreign_amount_desc(&self) -> Option<String> {
+        let currency = self.original_currency.as_ref()?;
+        let original = self.original_amount?;
+        Some(match self.get_exchange_rate() {
+            Some(rate) => format!("{:.2} {} @ {:.4}", original, currency, rate),
+            None => format!("{:.2} {}", original, currency),
+        })
+    }
+
+    // Rows with the same key in overlapping exports are the same bank transaction
+    fn duplication_key(&self) -> String {
+        format!(
+            "{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}",
+            self.date,
+            self.booking_date,
+            self.amount,
+            self.description,
+            self.other_account,
+            self.other_account_name,
+            self.reference
+        )
+    }
+
     pub fn get_other_account_desc(&self) -> String {
         match (&self.other_account, &self.other_account_name) {
             (Some(acc), Some(name)) => {
@@ -92,70 +171,288 @@ impl ExternalTransaction {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct StatementBalance {
+    pub date: Option<NaiveDate>,
+    pub amount: f64,
+}
+
+impl fmt::Display for StatementBalance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(date) = self.date {
+            write!(f, "{} ", date.format("%Y-%m-%d"))?;
+        }
+        write!(f, "{:.2}", self.amount)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueKind {
+    // The row is not imported at all
+    Rejected,
+    // An expected non-data row, like a header, summary or footer, it is not an error
+    Skipped,
+    // The row is imported, but some of its values are missing
+    Incomplete,
+}
+
+#[derive(Debug, Clone)]
+pub struct RowIssue {
+    pub row: usize,
+    pub kind: IssueKind,
+    pub reason: String,
+}
+
+impl fmt::Display for RowIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            IssueKind::Rejected => "rejected",
+            IssueKind::Skipped => "skipped",
+            IssueKind::Incomplete => "incomplete",
+        };
+        write!(f, "row {} {}: {}", self.row, kind, self.reason)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Statement {
+    pub transactions: Vec<ExternalTransaction>,
+    pub opening_balance: Option<StatementBalance>,
+    pub closing_balance: Option<StatementBalance>,
+    pub issues: Vec<RowIssue>,
+}
+
+impl Statement {
+    // The rows, which make the import fail in strict mode
+    pub fn malformed_rows(&self) -> usize {
+        self.issues
+            .iter()
+            .filter(|issue| issue.kind != IssueKind::Skipped)
+            .count()
+    }
+
+    // The dates are extracted before the normalization could remove them from the descriptions.
+    pub fn prepare(&mut self, dates: &DateExtractor, normalizer: &Normalizer) {
+        for transaction in &mut self.transactions {
+            if transaction.textual_date.is_none() {
+                transaction.textual_date = transaction.description.as_ref().and_then(|text| {
+                    dates.extract(text, transaction.date.or(transaction.booking_date))
+                });
+            }
+            normalizer.apply(transaction);
+        }
+    }
+}
+
 #[derive(Debug)]
-pub struct ExternalTransactionList(
-    pub Vec<ExternalTransaction>,
-    pub Option<NaiveDate>,
-    pub Option<NaiveDate>,
-);
+pub struct ExternalTransactionList {
+    pub transactions: Vec<ExternalTransaction>,
+    pub min_date: Option<NaiveDate>,
+    pub max_date: Option<NaiveDate>,
+    pub opening_balance: Option<StatementBalance>,
+    pub closing_balance: Option<StatementBalance>,
+}
+
+// The files, and the sheets in them, to read the external transactions from.
+pub struct InputSource {
+    pub input_files: Vec<String>,
+    pub sheet_name: Option<String>,
+    pub all_sheets: bool,
+    // Abort on the first malformed row, instead of importing what is possible
+    pub strict: bool,
+    // Overrides the date patterns of the format
+    pub date_formats: Vec<String>,
+    // Overrides the decimal separator of the format
+    pub decimal_separator: Option<char>,
+    // Overrides the textual date patterns of the format
+    pub textual_date_patterns: Vec<String>,
+    // Overrides the text normalization of the format
+    pub normalize: NormalizerOverrides,
+}
 
 pub struct SheetDefinition {
     //    input_file: String,
     workbook: Sheets<BufReader<File>>,
 }
 
+const DEFAULT_TEXTUAL_DATES: &[&str] = &[ISO_TIMESTAMP, YMD_DOTTED, DMY_DOTTED];
+
 pub trait SheetFormat {
-    fn parse_sheet(&self, range: &Range<DataType>) -> Vec<ExternalTransaction>;
+    // How the dates and amounts are written in this format
+    fn cell_converter(&self) -> CellConverter;
+    // How the descriptions and counterparty names are cleaned up
+    fn normalizer(&self) -> Normalizer {
+        Normalizer::default()
+    }
+    // Patterns of the spending date inside the descriptions
+    fn textual_date_patterns(&self) -> &'static [&'static str] {
+        DEFAULT_TEXTUAL_DATES
+    }
+    // Which dates are used for matching and as the post date
+    fn date_order(&self) -> DateOrder {
+        DateOrder::default()
+    }
+    fn parse_sheet(&self, range: &Range<DataType>, cells: &CellConverter) -> Result<Statement>;
 }
 
-impl SheetDefinition {
-    pub fn new(input_file: &str) -> Result<Self> {
-        let workbook = open_workbook_auto(input_file)?; //.expect("Cannot open file");
-        Ok(SheetDefinition {
-            // input_file,
-            workbook,
-        })
+// Formats which are not spreadsheets, but text or xml files, like bank statements.
+pub trait StatementFormat {
+    fn normalizer(&self) -> Normalizer {
+        Normalizer::default()
+    }
+    fn textual_date_patterns(&self) -> &'static [&'static str] {
+        DEFAULT_TEXTUAL_DATES
+    }
+    fn date_order(&self) -> DateOrder {
+        DateOrder::default()
+    }
+    fn parse_statement(&self, content: &str) -> Result<Statement>;
+}
+
+pub enum InputFormat {
+    Sheet(Box<dyn SheetFormat>),
+    Statement(Box<dyn StatementFormat>),
+}
+
+impl InputFormat {
+    pub fn normalizer(&self) -> Normalizer {
+        match self {
+            InputFormat::Sheet(sheet_format) => sheet_format.normalizer(),
+            InputFormat::Statement(statement_format) => statement_format.normalizer(),
+        }
+    }
+
+    pub fn date_order(&self) -> DateOrder {
+        match self {
+            InputFormat::Sheet(sheet_format) => sheet_format.date_order(),
+            InputFormat::Statement(statement_format) => statement_format.date_order(),
+        }
+    }
+
+    pub fn textual_date_patterns(&self) -> &'static [&'static str] {
+        match self {
+            InputFormat::Sheet(sheet_format) => sheet_format.textual_date_patterns(),
+            InputFormat::Statement(statement_format) => statement_format.textual_date_patterns(),
+        }
+    }
+}
+
+impl ExternalTransactionList {
+    pub fn new(transactions: Vec<ExternalTransaction>, matching: &DateOrder) -> Self {
+        let (min_date, max_date) = ExternalTransactionList::find_min_max(&transactions, matching);
+        ExternalTransactionList {
+            transactions,
+            min_date,
+            max_date,
+            opening_balance: None,
+            closing_balance: None,
+        }
     }
 
     pub fn load(
-        &mut self,
-        maybe_sheet_name: &Option<String>,
-        matching: Matching,
-        format: &Box<dyn SheetFormat>,
+        input: &InputSource,
+        matching: &DateOrder,
+        format: &InputFormat,
         term: &Term,
-    ) -> Result<ExternalTransactionList> {
-        let sheet_name = match maybe_sheet_name {
-            Some(name) => name.to_owned(),
-            None => {
-                let sheet_names = self.workbook.sheet_names();
-                sheet_names.first().unwrap().to_owned()
-            }
-        };
-        if let Ok(sheet) = self.workbook.worksheet_range(&sheet_name) {
-            term.write_line(&format!("found sheet '{}'", style(sheet_name).blue()))?;
-            let trans = format.parse_sheet(&sheet);
-            let (min, max) = SheetDefinition::find_min_max(&trans, matching);
-            Ok(ExternalTransactionList(trans, min, max))
+    ) -> Result<Self> {
+        let mut sources = Vec::new();
+        let mut issue_count = 0;
+        let mut opening_balance: Option<StatementBalance> = None;
+        let mut closing_balance: Option<StatementBalance> = None;
+        let normalizer = input.normalize.apply(format.normalizer())?;
+        let dates = if input.textual_date_patterns.is_empty() {
+            DateExtractor::new(format.textual_date_patterns())?
         } else {
+            DateExtractor::new(&input.textual_date_patterns)?
+        };
+        for input_file in &input.input_files {
+            match format {
+                InputFormat::Sheet(sheet_format) => {
+                    let mut sheet_definition = SheetDefinition::new(input_file)?;
+                    let cells = sheet_format
+                        .cell_converter()
+                        .with_overrides(input.decimal_separator, &input.date_formats);
+                    let sheets = sheet_definition.load(
+                        &input.sheet_name,
+                        input.all_sheets,
+                        sheet_format.as_ref(),
+                        &cells,
+                        term,
+                    )?;
+                    for (sheet_name, mut statement) in sheets {
+                        statement.prepare(&dates, &normalizer);
+                        let source = format!("{}:{}", input_file, sheet_name);
+                        report_issues(&source, &statement.issues, term)?;
+                        issue_count += statement.malformed_rows();
+                        sources.push(statement.transactions);
+                    }
+                }
+                InputFormat::Statement(statement_format) => {
+                    let content = read_statement_file(input_file)?;
+                    let mut statement = statement_format.parse_statement(&content)?;
+                    statement.prepare(&dates, &normalizer);
+                    term.write_line(&format!(
+                        "found {} entries in '{}'",
+                        style(statement.transactions.len()).blue(),
+                        style(input_file).blue()
+                    ))?;
+                    report_issues(input_file, &statement.issues, term)?;
+                    issue_count += statement.malformed_rows();
+                    opening_balance = earliest_balance(opening_balance, statement.opening_balance);
+                    closing_balance = latest_balance(closing_balance, statement.closing_balance);
+                    sources.push(statement.transactions);
+                }
+            }
+        }
+        if input.strict && issue_count > 0 {
+            return Err(anyhow!(
+                "Found {} malformed rows, nothing is correlated in strict mode!",
+                issue_count
+            ));
+        }
+        let transactions = ExternalTransactionList::merge(sources);
+        if input.input_files.len() > 1 || input.all_sheets {
             term.write_line(&format!(
-                "Sheet '{}' not found, no transactions will be imported!",
-                style(&sheet_name).red()
+                "{} distinct transactions after merging",
+                style(transactions.len()).blue()
             ))?;
-            Err(anyhow!(
-                "Sheet '{}' not found, no transactions will be imported!",
-                sheet_name
-            ))
         }
+        let mut result = ExternalTransactionList::new(transactions, matching);
+        result.opening_balance = opening_balance;
+        result.closing_balance = closing_balance;
+        Ok(result)
+    }
+
+    // Rows which appear in more than one source are kept only as many times as
+    // the most occurrences in a single source, so repeated, but legitimate rows
+    // within the same export are not lost.
+    fn merge(sources: Vec<Vec<ExternalTransaction>>) -> Vec<ExternalTransaction> {
+        let mut kept: HashMap<String, usize> = HashMap::new();
+        let mut result = Vec::new();
+        for source in sources {
+            let mut seen: HashMap<String, usize> = HashMap::new();
+            for transaction in source {
+                let key = transaction.duplication_key();
+                let count = seen.entry(key.clone()).or_default();
+                *count += 1;
+                let already_kept = kept.entry(key).or_default();
+                if *count > *already_kept {
+                    *already_kept = *count;
+                    result.push(transaction);
+                }
+            }
+        }
+        result
     }
 
     fn find_min_max(
         transactions: &[ExternalTransaction],
-        matching: Matching,
+        matching: &DateOrder,
     ) -> (Option<NaiveDate>, Option<NaiveDate>) {
         transactions
             .iter()
             .fold((None, None), |(min, max), current| {
-                let maybe_current_date = current.get_matching_date(matching);
+                let maybe_current_date = current.get_date_by(matching);
                 match maybe_current_date {
                     Some(current_date) => {
                         let new_min = match min {
@@ -174,6 +471,106 @@ impl SheetDefinition {
     }
 }
 
+// Prints the issues, and returns the number of malformed rows among them
+fn report_issues(source: &str, issues: &[RowIssue], term: &Term) -> Result<()> {
+    for issue in issues {
+        let styled = match issue.kind {
+            IssueKind::Rejected => style(issue).red(),
+            IssueKind::Skipped => style(issue).dim(),
+            IssueKind::Incomplete => style(issue).yellow(),
+        };
+        term.write_line(&format!("{} {}", style(source).blue(), styled))?;
+    }
+    Ok(())
+}
+
+fn earliest_balance(
+    current: Option<StatementBalance>,
+    other: Option<StatementBalance>,
+) -> Option<StatementBalance> {
+    match (current, other) {
+        (Some(a), Some(b)) => Some(if b.date < a.date { b } else { a }),
+        (a, b) => a.or(b),
+    }
+}
+
+fn latest_balance(
+    current: Option<StatementBalance>,
+    other: Option<StatementBalance>,
+) -> Option<StatementBalance> {
+    match (current, other) {
+        (Some(a), Some(b)) => Some(if b.date >= a.date { b } else { a }),
+        (a, b) => a.or(b),
+    }
+}
+
+// Statements are usually UTF-8, but older exports are still in Latin-1.
+fn read_statement_file(input_file: &str) -> Result<String> {
+    let bytes = fs::read(input_file)?;
+    match String::from_utf8(bytes) {
+        Ok(content) => Ok(content),
+        Err(err) => Ok(err.into_bytes().iter().map(|&b| b as char).collect()),
+    }
+}
+
+impl SheetDefinition {
+    pub fn new(input_file: &str) -> Result<Self> {
+        let workbook = open_workbook_auto(input_file)?; //.expect("Cannot open file");
+        Ok(SheetDefinition {
+            // input_file,
+            workbook,
+        })
+    }
+
+    pub fn load(
+        &mut self,
+        maybe_sheet_name: &Option<String>,
+        all_sheets: bool,
+        format: &dyn SheetFormat,
+        cells: &CellConverter,
+        term: &Term,
+    ) -> Result<Vec<(String, Statement)>> {
+        let sheet_names = match maybe_sheet_name {
+            Some(name) => vec![name.to_owned()],
+            None if all_sheets => self.workbook.sheet_names().to_owned(),
+            None => {
+                let sheet_names = self.workbook.sheet_names();
+                vec![sheet_names.first().unwrap().to_owned()]
+            }
+        };
+        let mut result = Vec::new();
+        for sheet_name in sheet_names {
+            let statement = self.load_sheet(&sheet_name, format, cells, term)?;
+            result.push((sheet_name, statement));
+        }
+        Ok(result)
+    }
+
+    fn load_sheet(
+        &mut self,
+        sheet_name: &str,
+        format: &dyn SheetFormat,
+        cells: &CellConverter,
+        term: &Term,
+    ) -> Result<Statement> {
+        if let Ok(sheet) = self.workbook.worksheet_range(sheet_name) {
+            term.write_line(&format!("found sheet '{}'", style(sheet_name).blue()))?;
+            format
+                .parse_sheet(&sheet, cells)
+                .with_context(|| format!("Unable to read sheet '{}'", sheet_name))
+        } else {
+            term.write_line(&format!(
+                "Sheet '{}' not found, no transactions will be imported!",
+                style(&sheet_name).red()
+            ))?;
+            Err(anyhow!(
+                "Sheet '{}' not found, no transactions will be imported!",
+                sheet_name
+            ))
+        }
+    }
+}
+
 pub struct TransactionPairing {
     transaction: Transaction,
     split: Split,
@@ -192,6 +589,10 @@ impl TransactionPairing {
         self.split.is_equal_amount(amount)
     }
 
+    pub fn is_split(&self, split_guid: &str) -> bool {
+        self.split.guid == split_guid
+    }
+
     pub fn is_not_matched(&self) -> bool {
         self.external.borrow().is_none()
     }
@@ -207,3 +608,87 @@ impl fmt::Display for TransactionPairing {
         write!(f, "{} - {}", self.transaction, self.split)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transaction(day: u32, amount: f64) -> ExternalTransaction {
+        ExternalTransaction {
+            date: NaiveDate::from_ymd_opt(2023, 1, day),
+            amount: Some(amount),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_foreign_amount() {
+        let payment = ExternalTransaction {
+            original_currency: Some("GBP".to_owned()),
+            original_amount: Some(-10.0),
+            exchange_rate: Some(0.86),
+            ..transaction(5, -11.63)
+        };
+        assert_eq!(
+            payment.get_foreign_amount_desc().as_deref(),
+            Some("-10.00 GBP @ 1.1630")
+        );
+    }
+
+    #[test]
+    fn test_old_textual_date_is_not_used() {
+        let mut statement = Statement {
+            transactions: vec![ExternalTransaction {
+                description: Some("Invoice 2021.11.15 INV-42".to_owned()),
+                ..transaction(10, -5.0)
+            }],
+            ..Default::default()
+        };
+        let dates = DateExtractor::new(DEFAULT_TEXTUAL_DATES).unwrap();
+        statement.prepare(&dates, &Normalizer::default());
+        let payment = &statement.transactions[0];
+        assert_eq!(payment.textual_date, None);
+        assert_eq!(
+            payment.get_date_by(&DateOrder::default()),
+            NaiveDate::from_ymd_opt(2023, 1, 10)
+        );
+    }
+
+    #[test]
+    fn test_date_order_fallback() {
+        let payment = ExternalTransaction {
+            booking_date: NaiveDate::from_ymd_opt(2023, 1, 6),
+            ..transaction(5, -2.0)
+        };
+        assert_eq!(
+            payment.get_date_by(&DateOrder::default()),
+            NaiveDate::from_ymd_opt(2023, 1, 5)
+        );
+        assert_eq!(
+            payment.get_date_by(&DateOrder(vec![DateKind::Booking, DateKind::Value])),
+            NaiveDate::from_ymd_opt(2023, 1, 6)
+        );
+        let unbooked = transaction(5, -2.0);
+        assert_eq!(
+            unbooked.get_date_by(&DateOrder(vec![DateKind::Booking, DateKind::Value])),
+            NaiveDate::from_ymd_opt(2023, 1, 5)
+        );
+    }
+
+    #[test]
+    fn test_merge_overlapping_sources() {
+        let january = vec![
+            transaction(30, -5.0),
+            transaction(31, -3.0),
+            transaction(31, -3.0),
+        ];
+        let february = vec![
+            transaction(31, -3.0),
+            transaction(31, -3.0),
+            transaction(1, 10.0),
+        ];
+        let merged = ExternalTransactionList::merge(vec![january, february]);
+        assert_eq!(merged.len(), 4);
+        assert_eq!(merged[3].amount, Some(10.0));
+    }
+}
@@ -1,9 +1,15 @@
-use crate::external_models::{ExternalTransaction, SheetFormat};
+use crate::camt::CamtFormat;
+use crate::external_models::{
+    ExternalTransaction, InputFormat, IssueKind, RowIssue, SheetFormat, Statement,
+};
+use crate::mt940::Mt940Format;
+use crate::normalize::{NormalizeStep, Normalizer};
 use crate::sheets::{
-    cell_to_date, cell_to_datetime, cell_to_english_date, cell_to_float, cell_to_german_date,
-    cell_to_iso_date, cell_to_string,
+    cell_to_string, BoundRow, CellConverter, Column, ColumnBinding, ENGLISH_DATE, GERMAN_DATE,
+    HUNGARIAN_DATE, HUNGARIAN_DATETIME, ISO_DATE,
 };
-use crate::utils::extract_date;
+use crate::utils::{DMY_DOTTED, DM_DOTTED_TIME, YMD_DOTTED};
+use anyhow::Result;
 use calamine::{DataType, Range};
 
 struct OtpFormat;
@@ -13,73 +19,344 @@ struct BankAustriaFormat;
 struct TransferwiseFormat;
 struct MagnetFormat;
 
-pub fn create_format(name: &Option<String>) -> Option<Box<dyn SheetFormat>> {
+pub fn create_format(name: &Option<String>) -> Option<InputFormat> {
     if let Some(ref format_name) = name {
         match format_name.to_lowercase().as_ref() {
-            "otp" => Some(Box::new(OtpFormat {})),
-            "otp2020" => Some(Box::new(OtpFormat2020 {})),
-            "granit" => Some(Box::new(GranitFormat {})),
-            "bankaustria" => Some(Box::new(BankAustriaFormat {})),
-            "transferwise" => Some(Box::new(TransferwiseFormat {})),
-            "magnet" => Some(Box::new(MagnetFormat {})),
+            "otp" => Some(InputFormat::Sheet(Box::new(OtpFormat {}))),
+            "otp2020" => Some(InputFormat::Sheet(Box::new(OtpFormat2020 {}))),
+            "granit" => Some(InputFormat::Sheet(Box::new(GranitFormat {}))),
+            "bankaustria" => Some(InputFormat::Sheet(Box::new(BankAustriaFormat {}))),
+            "mt940" => Some(InputFormat::Statement(Box::new(Mt940Format {}))),
+            "transferwise" => Some(InputFormat::Sheet(Box::new(TransferwiseFormat {}))),
+            "magnet" => Some(InputFormat::Sheet(Box::new(MagnetFormat {}))),
+            "camt" | "camt053" | "camt052" => Some(InputFormat::Statement(Box::new(CamtFormat {}))),
             _ => None,
         }
     } else {
-        Some(Box::new(OtpFormat {}))
+        Some(InputFormat::Sheet(Box::new(OtpFormat {})))
+    }
+}
+
+// Describes the columns of a sheet, and which rows are skipped. The positions of the
+// columns are the layouts of the exports, used when a sheet has no header row.
+struct RowLayout<'a> {
+    columns: &'a [Column],
+    skip_reason: &'a str,
+    // A row with a valid date here is a transaction, even if the format doesn't accept it
+    date_column: &'a str,
+}
+
+fn parse_rows<A, C>(
+    range: &Range<DataType>,
+    converter: &CellConverter,
+    layout: RowLayout,
+    accept: A,
+    convert: C,
+) -> Result<Statement>
+where
+    A: Fn(&BoundRow) -> bool,
+    C: Fn(&BoundRow) -> ExternalTransaction,
+{
+    let binding = ColumnBinding::find(range, layout.columns)?;
+    let first_row = range.start().map_or(0, |(row, _)| row as usize);
+    let mut statement = Statement::default();
+    for (idx, cells) in range.rows().enumerate().skip(binding.first_data_row()) {
+        let row_number = first_row + idx + 1;
+        let row = binding.bind(cells);
+        if row.is_empty() {
+            continue;
+        }
+        if !accept(&row) {
+            // summaries and footers have no dates, but a dated row is a malformed transaction
+            let kind = if converter.parse_date(row.get(layout.date_column)).is_some() {
+                IssueKind::Rejected
+            } else {
+                IssueKind::Skipped
+            };
+            statement.issues.push(RowIssue {
+                row: row_number,
+                kind,
+                reason: layout.skip_reason.to_owned(),
+            });
+            continue;
+        }
+        let transaction = convert(&row);
+        if transaction.date.is_none() {
+            statement.issues.push(RowIssue {
+                row: row_number,
+                kind: IssueKind::Incomplete,
+                reason: "date is missing or invalid".to_owned(),
+            });
+        }
+        if transaction.amount.is_none() {
+            statement.issues.push(RowIssue {
+                row: row_number,
+                kind: IssueKind::Incomplete,
+                reason: "amount is missing or invalid".to_owned(),
+            });
+        }
+        statement.transactions.push(transaction);
     }
+    Ok(statement)
 }
 
+const OTP_COLUMNS: &[Column] = &[
+    Column::required("account", &["Számlaszám", "Account number"]).at(0),
+    Column::optional(
+        "category",
+        &["Tranzakció típusa", "Típus", "Transaction type"],
+    )
+    .at(1),
+    Column::required("date", &["Tranzakció dátuma", "Transaction date"]).at(2),
+    Column::optional(
+        "booking_date",
+        &["Könyvelés dátuma", "Értéknap", "Booking date"],
+    )
+    .at(3),
+    Column::required("amount", &["Összeg", "Amount"]).at(4),
+    Column::optional(
+        "other_account",
+        &["Ellenoldali számlaszám", "Partner account"],
+    )
+    .at(6),
+    Column::optional("other_account_name", &["Ellenoldali név", "Partner name"]).at(7),
+    Column::optional("description", &["Közlemény", "Description"]).at(8),
+    Column::optional("original_amount", &["Eredeti összeg", "Original amount"]),
+    Column::optional(
+        "original_currency",
+        &["Eredeti devizanem", "Original currency"],
+    ),
+    Column::optional("exchange_rate", &["Árfolyam", "Exchange rate"]),
+    Column::optional(
+        "reference",
+        &["Referencia", "Tranzakció azonosító", "Reference number"],
+    ),
+];
+
+const OTP2020_COLUMNS: &[Column] = &[
+    Column::required("account", &["Számlaszám", "Account number"]).at(0),
+    Column::optional(
+        "category",
+        &["Tranzakció típusa", "Típus", "Transaction type"],
+    )
+    .at(1),
+    Column::required("date", &["Tranzakció időpontja", "Transaction time"]).at(2),
+    Column::optional(
+        "booking_date",
+        &["Könyvelés dátuma", "Értéknap", "Booking date"],
+    )
+    .at(3),
+    Column::required("amount", &["Összeg", "Amount"]).at(4),
+    Column::optional(
+        "other_account",
+        &["Ellenoldali számlaszám", "Partner account"],
+    )
+    .at(5),
+    Column::optional("other_account_name", &["Ellenoldali név", "Partner name"]).at(6),
+    Column::optional("description", &["Közlemény", "Description"]).at(7),
+    Column::optional("original_amount", &["Eredeti összeg", "Original amount"]),
+    Column::optional(
+        "original_currency",
+        &["Eredeti devizanem", "Original currency"],
+    ),
+    Column::optional("exchange_rate", &["Árfolyam", "Exchange rate"]),
+    Column::optional(
+        "reference",
+        &["Referencia", "Tranzakció azonosító", "Reference number"],
+    ),
+];
+
+const GRANIT_COLUMNS: &[Column] = &[
+    Column::required("amount", &["Összeg", "Amount"]).at(1),
+    Column::required("date", &["Értéknap", "Tranzakció dátuma", "Value date"]).at(4),
+    Column::optional("category", &["Tranzakció típusa", "Transaction type"]).at(6),
+    Column::optional(
+        "beneficiary_name",
+        &["Kedvezményezett neve", "Beneficiary name"],
+    )
+    .at(7),
+    Column::optional(
+        "beneficiary_account",
+        &["Kedvezményezett számlaszáma", "Beneficiary account"],
+    )
+    .at(8),
+    Column::optional("originator_name", &["Megbízó neve", "Originator name"]).at(9),
+    Column::optional("comment", &["Közlemény", "Narrative"]).at(11),
+    Column::optional(
+        "reference",
+        &["Tranzakció azonosító", "Referencia", "Transaction id"],
+    ),
+];
+
+const BANK_AUSTRIA_COLUMNS: &[Column] = &[
+    Column::required("booking_date", &["Buchungsdatum", "Booking date"]).at(1),
+    Column::optional(
+        "description",
+        &["Buchungstext", "Umsatztext", "Booking text"],
+    )
+    .at(3),
+    Column::required("amount", &["Betrag", "Amount"]).at(6),
+    Column::optional("payer_account", &["Auftraggeber IBAN", "Payer IBAN"]).at(9),
+    Column::optional("payee_account", &["Empfänger IBAN", "Payee IBAN"]).at(12),
+    Column::optional(
+        "reference",
+        &["Buchungsreferenz", "Zahlungsreferenz", "Booking reference"],
+    ),
+];
+
+const TRANSFERWISE_COLUMNS: &[Column] = &[
+    Column::required("date", &["Date"]).at(1),
+    Column::required("amount", &["Amount"]).at(2),
+    Column::optional("description", &["Description"]).at(4),
+    Column::optional("payee_name", &["Payee Name"]).at(11),
+    Column::optional("payee_account", &["Payee Account Number"]).at(12),
+    Column::optional("merchant", &["Merchant"]).at(13),
+    Column::optional("fee", &["Total fees"]).at(14),
+    Column::optional("currency", &["Currency"]),
+    Column::optional("exchange_from", &["Exchange From"]),
+    Column::optional("exchange_to", &["Exchange To"]),
+    Column::optional("exchange_rate", &["Exchange Rate"]),
+    Column::optional("exchange_to_amount", &["Exchange To Amount"]),
+    Column::optional("reference", &["TransferWise ID", "ID"]),
+];
+
+const MAGNET_COLUMNS: &[Column] = &[
+    Column::required("date", &["Tranzakció dátuma", "Transaction date"]).at(1),
+    Column::optional(
+        "booking_date",
+        &["Könyvelés dátuma", "Értéknap", "Booking date"],
+    )
+    .at(2),
+    Column::optional("other_account_name", &["Partner neve", "Partner name"]).at(3),
+    Column::optional("other_account", &["Partner számlaszáma", "Partner account"]).at(4),
+    Column::optional("description", &["Közlemény", "Description"]).at(5),
+    Column::required("amount", &["Összeg", "Amount"]).at(6),
+    Column::optional(
+        "reference",
+        &["Tranzakció azonosító", "Referencia", "Transaction id"],
+    ),
+];
+
 impl SheetFormat for OtpFormat {
-    fn parse_sheet(&self, range: &Range<DataType>) -> Vec<ExternalTransaction> {
-        range
-            .rows()
-            .filter(|row| row[0] != DataType::Empty)
-            .map(|row| {
-                let descrip = cell_to_string(&row[8]);
-                let parsed_date = extract_date(&descrip);
+    fn cell_converter(&self) -> CellConverter {
+        CellConverter::new(',', &[HUNGARIAN_DATE])
+    }
+
+    fn textual_date_patterns(&self) -> &'static [&'static str] {
+        &[YMD_DOTTED]
+    }
+
+    fn parse_sheet(&self, range: &Range<DataType>, cells: &CellConverter) -> Result<Statement> {
+        parse_rows(
+            range,
+            cells,
+            RowLayout {
+                columns: OTP_COLUMNS,
+                skip_reason: "account number is empty",
+                date_column: "date",
+            },
+            |row| *row.get("account") != DataType::Empty,
+            |row| {
+                let descrip = cell_to_string(row.get("description"));
                 ExternalTransaction {
-                    date: cell_to_date(&row[2]),
-                    booking_date: cell_to_date(&row[3]),
-                    amount: cell_to_float(&row[4]),
-                    category: cell_to_string(&row[1]),
+                    date: cells.parse_date(row.get("date")),
+                    booking_date: cells.parse_date(row.get("booking_date")),
+                    amount: cells.parse_amount(row.get("amount")),
+                    category: cell_to_string(row.get("category")),
                     description: descrip,
-                    other_account: cell_to_string(&row[6]),
-                    other_account_name: cell_to_string(&row[7]),
-                    textual_date: parsed_date,
+                    other_account: cell_to_string(row.get("other_account")),
+                    other_account_name: cell_to_string(row.get("other_account_name")),
+                    textual_date: None,
                     transaction_fee: None,
+                    reference: cell_to_string(row.get("reference")),
+                    original_currency: cell_to_string(row.get("original_currency")),
+                    original_amount: with_sign_of(
+                        cells.parse_amount(row.get("original_amount")),
+                        cells.parse_amount(row.get("amount")),
+                    ),
+                    exchange_rate: cells.parse_amount(row.get("exchange_rate")),
                 }
-            })
-            .collect()
+            },
+        )
     }
 }
 
 impl SheetFormat for OtpFormat2020 {
-    fn parse_sheet(&self, range: &Range<DataType>) -> Vec<ExternalTransaction> {
-        range
-            .rows()
-            .filter(|row| row[0] != DataType::Empty)
-            .map(|row| {
-                let spend_date = cell_to_datetime(&row[2]);
-                let description = cell_to_string(&row[7]);
-                let parsed_date = extract_date(&description);
+    fn cell_converter(&self) -> CellConverter {
+        CellConverter::new(',', &[HUNGARIAN_DATETIME, HUNGARIAN_DATE])
+    }
+
+    fn textual_date_patterns(&self) -> &'static [&'static str] {
+        &[YMD_DOTTED]
+    }
+
+    fn parse_sheet(&self, range: &Range<DataType>, cells: &CellConverter) -> Result<Statement> {
+        parse_rows(
+            range,
+            cells,
+            RowLayout {
+                columns: OTP2020_COLUMNS,
+                skip_reason: "account number is empty",
+                date_column: "date",
+            },
+            |row| *row.get("account") != DataType::Empty,
+            |row| {
+                let spend_date = cells.parse_datetime(row.get("date"));
+                let description = cell_to_string(row.get("description"));
                 ExternalTransaction {
                     date: spend_date.map(|datetime| datetime.date()),
-                    booking_date: cell_to_date(&row[3]),
-                    amount: cell_to_float(&row[4]),
-                    category: cell_to_string(&row[1]),
+                    booking_date: cells.parse_date(row.get("booking_date")),
+                    amount: cells.parse_amount(row.get("amount")),
+                    category: cell_to_string(row.get("category")),
                     description,
-                    other_account: cell_to_string(&row[5]),
-                    other_account_name: cell_to_string(&row[6]),
-                    textual_date: parsed_date,
+                    other_account: cell_to_string(row.get("other_account")),
+                    other_account_name: cell_to_string(row.get("other_account_name")),
+                    textual_date: None,
                     transaction_fee: None,
+                    reference: cell_to_string(row.get("reference")),
+                    original_currency: cell_to_string(row.get("original_currency")),
+                    original_amount: with_sign_of(
+                        cells.parse_amount(row.get("original_amount")),
+                        cells.parse_amount(row.get("amount")),
+                    ),
+                    exchange_rate: cells.parse_amount(row.get("exchange_rate")),
                 }
-            })
-            .collect()
+            },
+        )
+    }
+}
+
+// Some banks show the original amount without sign
+fn with_sign_of(value: Option<f64>, amount: Option<f64>) -> Option<f64> {
+    match (value, amount) {
+        (Some(value), Some(amount)) => Some(value.abs().copysign(amount)),
+        (value, _) => value,
     }
 }
 
-fn is_float(dt: &DataType) -> bool {
-    matches!(dt, DataType::Float(_))
+// Conversions show both currencies, the original is the one which is not the currency of the row.
+// The rate tells how much 'Exchange To' is one 'Exchange From'.
+fn transferwise_original(row: &BoundRow, cells: &CellConverter) -> (Option<String>, Option<f64>) {
+    let amount = cells.parse_amount(row.get("amount"));
+    let currency = cell_to_string(row.get("currency"));
+    let from = cell_to_string(row.get("exchange_from"));
+    let to = cell_to_string(row.get("exchange_to"));
+    let rate = cells
+        .parse_amount(row.get("exchange_rate"))
+        .filter(|r| *r != 0.0);
+    match (from, to, amount, rate) {
+        (Some(from), Some(to), Some(amount), Some(rate)) if from != to => {
+            if currency.as_ref() == Some(&to) {
+                (Some(from), Some(amount / rate))
+            } else {
+                let converted = cells
+                    .parse_amount(row.get("exchange_to_amount"))
+                    .unwrap_or(amount * rate);
+                (Some(to), with_sign_of(Some(converted), Some(amount)))
+            }
+        }
+        _ => (None, None),
+    }
 }
 
 fn concat(first: &Option<String>, second: &Option<String>) -> Option<String> {
@@ -96,131 +373,170 @@ fn concat(first: &Option<String>, second: &Option<String>) -> Option<String> {
     }
 }
 
-fn cleanup_string(input: &str) -> String {
-    let casefix = if input.to_uppercase() == input {
-        input.to_lowercase()
-    } else {
-        input.to_owned()
-    };
-    casefix
-        .replace("A'", "Á")
-        .replace("I'", "Í")
-        .replace("E'", "É")
-        .replace("O'", "Ó")
-        .replace("U'", "Ú")
-        .replace("U:", "Ü")
-        .replace("O:", "Ö")
-        .replace("a'", "á")
-        .replace("i'", "í")
-        .replace("e'", "é")
-        .replace("o'", "ó")
-        .replace("u'", "ú")
-        .replace("u:", "ü")
-        .replace("o:", "ö")
-}
-
 impl SheetFormat for GranitFormat {
-    fn parse_sheet(&self, range: &Range<DataType>) -> Vec<ExternalTransaction> {
-        range
-            .rows()
-            .filter(|row| is_float(&row[1]))
-            .map(|row| {
-                let date = cell_to_iso_date(&row[4]);
-                let other_account_name = cell_to_string(&row[7])
-                    .or_else(|| cell_to_string(&row[9]))
-                    .map(|name| cleanup_string(&name));
-                let comment = cell_to_string(&row[11]);
-                //.map(|x| x.replace("****1683",""));
-                //println!("Row is {:?} -> date {:?} comment {:?} other_name: {:?}", row, date, comment, other_account_name);
+    fn cell_converter(&self) -> CellConverter {
+        CellConverter::new(',', &[ISO_DATE])
+    }
+
+    // the names are exported in capitals, with the accents written as A' or O:, but the
+    // comment is kept as it is
+    fn normalizer(&self) -> Normalizer {
+        Normalizer::default().with_name_steps(&[
+            NormalizeStep::Casefold,
+            NormalizeStep::Accents,
+            NormalizeStep::Whitespace,
+        ])
+    }
+
+    fn parse_sheet(&self, range: &Range<DataType>, cells: &CellConverter) -> Result<Statement> {
+        parse_rows(
+            range,
+            cells,
+            RowLayout {
+                columns: GRANIT_COLUMNS,
+                skip_reason: "amount is not a number",
+                date_column: "date",
+            },
+            |row| cells.parse_amount(row.get("amount")).is_some(),
+            |row| {
+                let date = cells.parse_date(row.get("date"));
+                let other_account_name = cell_to_string(row.get("beneficiary_name"))
+                    .or_else(|| cell_to_string(row.get("originator_name")));
+                let comment = cell_to_string(row.get("comment"));
                 ExternalTransaction {
                     date,
                     booking_date: None,
-                    amount: cell_to_float(&row[1]),
-                    category: cell_to_string(&row[6]),
+                    amount: cells.parse_amount(row.get("amount")),
+                    category: cell_to_string(row.get("category")),
                     description: concat(&other_account_name, &comment),
-                    other_account: cell_to_string(&row[8]), //.or_else(|| cell_to_string(&row[10])),
+                    other_account: cell_to_string(row.get("beneficiary_account")),
                     other_account_name,
                     textual_date: None,
                     transaction_fee: None,
+                    reference: cell_to_string(row.get("reference")),
+                    ..Default::default()
                 }
-            })
-            .collect()
+            },
+        )
     }
 }
 
 impl SheetFormat for BankAustriaFormat {
-    fn parse_sheet(&self, range: &Range<DataType>) -> Vec<ExternalTransaction> {
-        range
-            .rows()
-            .skip(1)
-            .filter(|row| is_float(&row[6]))
-            .map(|row| {
-                let date = cell_to_german_date(&row[1]);
-                let booking_date = cell_to_german_date(&row[1]);
-                let amount = cell_to_float(&row[6]).unwrap();
+    fn cell_converter(&self) -> CellConverter {
+        CellConverter::new(',', &[GERMAN_DATE])
+    }
+
+    // Card payments are described like 'POS 12.03. UM 14.22'
+    fn textual_date_patterns(&self) -> &'static [&'static str] {
+        &[DMY_DOTTED, DM_DOTTED_TIME]
+    }
+
+    fn parse_sheet(&self, range: &Range<DataType>, cells: &CellConverter) -> Result<Statement> {
+        parse_rows(
+            range,
+            cells,
+            RowLayout {
+                columns: BANK_AUSTRIA_COLUMNS,
+                skip_reason: "amount is not a number",
+                date_column: "booking_date",
+            },
+            |row| cells.parse_amount(row.get("amount")).is_some(),
+            |row| {
+                let date = cells.parse_date(row.get("booking_date"));
+                let booking_date = cells.parse_date(row.get("booking_date"));
+                let amount = cells.parse_amount(row.get("amount")).unwrap();
                 let other_account = if amount < 0.0 {
-                    cell_to_string(&row[12])
+                    cell_to_string(row.get("payee_account"))
                 } else {
-                    cell_to_string(&row[9])
+                    cell_to_string(row.get("payer_account"))
                 };
                 ExternalTransaction {
                     date,
                     booking_date,
                     amount: Some(amount),
                     category: None,
-                    description: cell_to_string(&row[3]).map(|s| s.trim().to_owned()),
+                    description: cell_to_string(row.get("description"))
+                        .map(|s| s.trim().to_owned()),
                     other_account,
                     other_account_name: None,
                     textual_date: None,
                     transaction_fee: None,
+                    reference: cell_to_string(row.get("reference")),
+                    ..Default::default()
                 }
-            })
-            .collect()
+            },
+        )
     }
 }
 
 impl SheetFormat for TransferwiseFormat {
-    fn parse_sheet(&self, range: &Range<DataType>) -> Vec<ExternalTransaction> {
-        range
-            .rows()
-            .skip(1)
-            .filter(|row| is_float(&row[2]))
-            .map(|row| {
-                let date = cell_to_english_date(&row[1]);
-                let amount = cell_to_float(&row[2]);
-                let other_account_name =
-                    cell_to_string(&row[13]).or_else(|| cell_to_string(&row[11]));
-                let other_account = cell_to_string(&row[12]);
+    fn cell_converter(&self) -> CellConverter {
+        CellConverter::new('.', &[ENGLISH_DATE])
+    }
+
+    fn parse_sheet(&self, range: &Range<DataType>, cells: &CellConverter) -> Result<Statement> {
+        parse_rows(
+            range,
+            cells,
+            RowLayout {
+                columns: TRANSFERWISE_COLUMNS,
+                skip_reason: "amount is not a number",
+                date_column: "date",
+            },
+            |row| cells.parse_amount(row.get("amount")).is_some(),
+            |row| {
+                let date = cells.parse_date(row.get("date"));
+                let amount = cells.parse_amount(row.get("amount"));
+                let other_account_name = cell_to_string(row.get("merchant"))
+                    .or_else(|| cell_to_string(row.get("payee_name")));
+                let other_account = cell_to_string(row.get("payee_account"));
+                let (original_currency, original_amount) = transferwise_original(row, cells);
 
                 ExternalTransaction {
                     date,
                     booking_date: None,
                     amount,
                     category: None,
-                    description: cell_to_string(&row[4]).map(|s| s.trim().to_owned()),
+                    description: cell_to_string(row.get("description"))
+                        .map(|s| s.trim().to_owned()),
                     other_account,
                     other_account_name,
                     textual_date: None,
-                    transaction_fee: cell_to_float(&row[14]).filter(|value| *value > 0.0),
+                    transaction_fee: cells
+                        .parse_amount(row.get("fee"))
+                        .filter(|value| *value > 0.0),
+                    reference: cell_to_string(row.get("reference")),
+                    original_currency,
+                    original_amount,
+                    exchange_rate: cells.parse_amount(row.get("exchange_rate")),
                 }
-            })
-            .collect()
+            },
+        )
     }
 }
 
 impl SheetFormat for MagnetFormat {
-    fn parse_sheet(&self, range: &Range<DataType>) -> Vec<ExternalTransaction> {
-        range
-            .rows()
-            .skip(1)
-            .filter(|row| is_float(&row[6]))
-            .map(|row| {
-                let date = cell_to_date(&row[1]);
-                let booking_date = cell_to_date(&row[2]);
-                let amount = cell_to_float(&row[6]);
-                let other_account = cell_to_string(&row[4]);
-                let other_account_name = cell_to_string(&row[3]);
-                let description = cell_to_string(&row[5]);
+    fn cell_converter(&self) -> CellConverter {
+        CellConverter::new(',', &[HUNGARIAN_DATE])
+    }
+
+    fn parse_sheet(&self, range: &Range<DataType>, cells: &CellConverter) -> Result<Statement> {
+        parse_rows(
+            range,
+            cells,
+            RowLayout {
+                columns: MAGNET_COLUMNS,
+                skip_reason: "amount is not a number",
+                date_column: "date",
+            },
+            |row| cells.parse_amount(row.get("amount")).is_some(),
+            |row| {
+                let date = cells.parse_date(row.get("date"));
+                let booking_date = cells.parse_date(row.get("booking_date"));
+                let amount = cells.parse_amount(row.get("amount"));
+                let other_account = cell_to_string(row.get("other_account"));
+                let other_account_name = cell_to_string(row.get("other_account_name"));
+                let description = cell_to_string(row.get("description"));
 
                 ExternalTransaction {
                     date,
@@ -232,8 +548,146 @@ impl SheetFormat for MagnetFormat {
                     other_account_name,
                     textual_date: None,
                     transaction_fee: None,
+                    reference: cell_to_string(row.get("reference")),
+                    ..Default::default()
                 }
-            })
-            .collect()
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn sheet(rows: &[&[&str]]) -> Range<DataType> {
+        let width = rows.iter().map(|row| row.len()).max().unwrap_or(1) as u32;
+        let mut range = Range::new((0, 0), (rows.len() as u32 - 1, width - 1));
+        for (row_idx, row) in rows.iter().enumerate() {
+            for (col_idx, value) in row.iter().enumerate() {
+                if !value.is_empty() {
+                    range.set_value(
+                        (row_idx as u32, col_idx as u32),
+                        DataType::String(value.to_string()),
+                    );
+                }
+            }
+        }
+        range
+    }
+
+    #[test]
+    fn test_summary_rows_are_skipped() {
+        let range = sheet(&[
+            &["Összeg", "Értéknap", "Közlemény"],
+            &["-1 200,00", "2023-01-10", "Tesco"],
+            &["Összesen:", "", ""],
+        ]);
+        let format = GranitFormat {};
+        let statement = format
+            .parse_sheet(&range, &format.cell_converter())
+            .unwrap();
+        assert_eq!(statement.transactions.len(), 1);
+        assert_eq!(statement.issues.len(), 1);
+        assert_eq!(statement.issues[0].kind, IssueKind::Skipped);
+        assert_eq!(statement.malformed_rows(), 0);
+    }
+
+    #[test]
+    fn test_malformed_amount_fails_strict_mode() {
+        let range = sheet(&[
+            &["Összeg", "Értéknap", "Közlemény"],
+            &["-1 200,00", "2023-01-10", "Tesco"],
+            &["12.50", "2023-01-11", "Spar"],
+        ]);
+        let format = GranitFormat {};
+        let statement = format
+            .parse_sheet(&range, &format.cell_converter())
+            .unwrap();
+        assert_eq!(statement.transactions.len(), 1);
+        assert_eq!(statement.issues[0].row, 3);
+        assert_eq!(statement.issues[0].kind, IssueKind::Rejected);
+        assert_eq!(statement.malformed_rows(), 1);
+    }
+
+    #[test]
+    fn test_renamed_header_is_reported() {
+        let range = sheet(&[
+            &[
+                "Dátum",
+                "Tranzakció dátuma",
+                "Könyvelés dátuma",
+                "Partner neve",
+            ],
+            &["", "2023.01.10", "2023.01.11", "Tesco"],
+        ]);
+        let err = ColumnBinding::find(&range, MAGNET_COLUMNS).unwrap_err();
+        assert!(err.to_string().contains("amount (Összeg/Amount)"));
+    }
+
+    #[test]
+    fn test_granit_cleans_only_the_name() {
+        let range = sheet(&[
+            &["Összeg", "Értéknap", "Kedvezményezett neve", "Közlemény"],
+            &[
+                "-1 200,00",
+                "2023-01-10",
+                "KO:RTE'FA KFT",
+                "Invoice for O'Brien",
+            ],
+        ]);
+        let format = GranitFormat {};
+        let mut statement = format
+            .parse_sheet(&range, &format.cell_converter())
+            .unwrap();
+        let normalizer = format.normalizer();
+        normalizer.apply(&mut statement.transactions[0]);
+        let transaction = &statement.transactions[0];
+        assert_eq!(
+            transaction.other_account_name.as_deref(),
+            Some("körtéfa kft")
+        );
+        assert_eq!(
+            transaction.description.as_deref(),
+            Some("körtéfa kft Invoice for O'Brien")
+        );
+    }
+
+    #[test]
+    fn test_headerless_otp_export() {
+        let range = sheet(&[
+            &[
+                "117730161111111100000000",
+                "Vásárlás",
+                "2023.01.10.",
+                "2023.01.11.",
+                "-1 200,00",
+                "",
+                "",
+                "TESCO",
+                "Vásárlás 2023.01.09",
+            ],
+            &["", "Záró egyenleg", "", "", "12 000,00"],
+        ]);
+        let format = OtpFormat {};
+        let statement = format
+            .parse_sheet(&range, &format.cell_converter())
+            .unwrap();
+        assert_eq!(statement.transactions.len(), 1);
+        let transaction = &statement.transactions[0];
+        assert_eq!(transaction.date, NaiveDate::from_ymd_opt(2023, 1, 10));
+        assert_eq!(
+            transaction.booking_date,
+            NaiveDate::from_ymd_opt(2023, 1, 11)
+        );
+        assert_eq!(transaction.amount, Some(-1200.0));
+        assert_eq!(transaction.other_account_name.as_deref(), Some("TESCO"));
+        assert_eq!(
+            transaction.description.as_deref(),
+            Some("Vásárlás 2023.01.09")
+        );
+        assert_eq!(statement.issues.len(), 1);
+        assert_eq!(statement.issues[0].kind, IssueKind::Skipped);
     }
 }
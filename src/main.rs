@@ -9,49 +9,85 @@ extern crate clap;
 extern crate calamine;
 extern crate chrono;
 extern crate console;
+extern crate glob;
 extern crate guid_create;
+extern crate quick_xml;
 extern crate regex;
 #[macro_use]
 extern crate anyhow;
 #[macro_use]
 extern crate lazy_static;
 
+mod camt;
 mod cli;
 pub mod correlator;
 mod dbmodifier;
 mod external_models;
 mod formats;
 pub mod models;
+mod mt940;
+mod normalize;
 mod query;
+mod reports;
 pub mod schema;
 mod sheets;
 pub mod utils;
 
+use std::convert::TryFrom;
 use std::io;
 
 use anyhow::{Context, Result};
+use chrono::{Datelike, Local, NaiveDate};
 use clap::{CommandFactory, Parser};
 use clap_complete::{generate, Shell};
-use cli::{Commands, CommoditiesArgs, CorrelateArgs, ListAccountsArgs, TransactionsArgs};
+use cli::{
+    BalanceArgs, BalanceSheetArgs, BudgetArgs, Commands, CommoditiesArgs, CorrelateArgs,
+    IncomeReportArgs, ListAccountsArgs, ReconciliationArgs, ReportCommands, SeriesArgs,
+    TransactionsArgs,
+};
 use console::{style, Term};
 
 use crate::cli::Cli;
 use crate::correlator::CorrelationCommand;
-use crate::external_models::Matching;
+use crate::external_models::{DateKind, DateOrder, InputSource};
 use crate::formats::create_format;
+use crate::models::Account;
+use crate::normalize::NormalizerOverrides;
+use crate::query::account_tree::AccountTree;
 use crate::query::accounts::ToAccountQuery;
+use crate::query::balances::{AccountBalances, BalanceDisplay};
 use crate::query::currencies::CommoditiesQuery;
+use crate::query::prices::PriceTable;
+use crate::query::text_match::TextMatcher;
 use crate::query::transactions::TransactionQuery;
-use crate::utils::establish_connection;
+use crate::reports::balance_sheet::BalanceSheet;
+use crate::reports::budget::BudgetPlan;
+use crate::reports::income::IncomeReport;
+use crate::reports::reconciliation::ReconciliationSummary;
+use crate::reports::series::Series;
+use crate::reports::Period;
+use crate::utils::{establish_connection, expand_input_files, parse_date_arg};
 
 fn main() {
     let cli = Cli::parse();
+    let matcher = TextMatcher {
+        mode: cli.match_mode,
+        case_sensitive: cli.case_sensitive,
+    };
 
     match cli.command {
-        Commands::ListAccounts(args) => handle_list_accounts(args),
-        Commands::Transactions(args) => handle_list_entries(args),
-        Commands::Commodities(args) => handle_commodities(args),
-        Commands::Correlate(args) => handle_correlate(args),
+        Commands::ListAccounts(args) => handle_list_accounts(args, matcher),
+        Commands::Transactions(args) => handle_list_entries(*args, matcher),
+        Commands::Commodities(args) => handle_commodities(args, matcher),
+        Commands::Balance(args) => handle_balance(args, matcher),
+        Commands::Budget(args) => handle_budget(args, matcher),
+        Commands::Report { report } => match report {
+            ReportCommands::Income(args) => handle_income_report(args),
+            ReportCommands::BalanceSheet(args) => handle_balance_sheet(args),
+            ReportCommands::Series(args) => handle_series(args, matcher),
+            ReportCommands::Reconciliation(args) => handle_reconciliation(args, matcher),
+        },
+        Commands::Correlate(args) => handle_correlate(*args, matcher),
         Commands::Completions { shell } => handle_shell_completions(shell),
     }
     .unwrap();
@@ -64,21 +100,180 @@ fn handle_shell_completions(shell: Shell) -> Result<usize> {
     Ok(0)
 }
 
-fn handle_list_accounts(args: ListAccountsArgs) -> Result<usize> {
+fn handle_list_accounts(args: ListAccountsArgs, matcher: TextMatcher) -> Result<usize> {
     let mut connection = establish_connection();
-    let q = args.account.build(args.limit);
-    q.execute_and_display(&mut connection);
+    let q = args.account.build(args.limit, matcher);
+    if args.tree {
+        let tree = AccountTree::load(&mut connection)?;
+        let roots = if q.has_filter() {
+            q.execute(&mut connection)?
+        } else {
+            Vec::new()
+        };
+        if q.has_filter() && roots.is_empty() {
+            return Err(anyhow!("No account matches: {}!", &q));
+        }
+        let count = tree.display(&roots.iter().collect::<Vec<_>>(), args.depth);
+        return Ok(count);
+    }
+    q.execute_and_display(&mut connection)?;
     Ok(0)
 }
 
-fn handle_list_entries(args: TransactionsArgs) -> Result<usize> {
+fn handle_balance(args: BalanceArgs, matcher: TextMatcher) -> Result<usize> {
+    let mut connection = establish_connection();
+    let as_of = parse_date_arg("balance", &args.date)?;
+    let q = args.account.build(Some(i64::MAX), matcher);
+    let tree = AccountTree::load(&mut connection)?;
+    let roots = if q.has_filter() {
+        q.execute(&mut connection)?
+    } else {
+        Vec::new()
+    };
+    if q.has_filter() && roots.is_empty() {
+        return Err(anyhow!("No account matches: {}!", &q));
+    }
+    let balances = AccountBalances::load(&mut connection, None, as_of);
+    let display = BalanceDisplay {
+        rollup: args.rollup,
+        raw_sign: args.raw_sign,
+    };
+    Ok(display.display(&tree, &balances, &roots.iter().collect::<Vec<_>>()))
+}
+
+fn handle_income_report(args: IncomeReportArgs) -> Result<usize> {
+    let mut connection = establish_connection();
+    let to = parse_date_arg("to", &args.to)?.unwrap_or_else(|| Local::now().date_naive());
+    let from = parse_date_arg("from", &args.from)?
+        .unwrap_or_else(|| NaiveDate::from_ymd_opt(to.year(), 1, 1).expect("First day of year"));
+    if from > to {
+        return Err(anyhow!(
+            "The period starts after it ends: {} - {}",
+            from,
+            to
+        ));
+    }
+    let tree = AccountTree::load(&mut connection)?;
+    let report = IncomeReport {
+        period: Period::new(from, to),
+        compare: args.compare,
+        monthly: args.monthly,
+        max_depth: args.depth,
+    };
+    Ok(report.display(&mut connection, &tree))
+}
+
+fn handle_balance_sheet(args: BalanceSheetArgs) -> Result<usize> {
+    let mut connection = establish_connection();
+    let date = parse_date_arg("report", &args.date)?.unwrap_or_else(|| Local::now().date_naive());
+    let currency = CommoditiesQuery::get_currency(&mut connection, &args.currency)
+        .with_context(|| format!("Unknown currency: '{}'!", args.currency))?;
+    let tree = AccountTree::load(&mut connection)?;
+    let balances = AccountBalances::load(&mut connection, None, Some(date));
+    let prices = PriceTable::load(&mut connection, date);
+    let report = BalanceSheet {
+        date,
+        currency: &currency,
+        max_depth: args.depth,
+    };
+    Ok(report.display(&tree, &balances, &prices))
+}
+
+fn handle_series(args: SeriesArgs, matcher: TextMatcher) -> Result<usize> {
+    let mut connection = establish_connection();
+    let to = parse_date_arg("to", &args.to)?.unwrap_or_else(|| Local::now().date_naive());
+    let from = parse_date_arg("from", &args.from)?
+        .unwrap_or_else(|| NaiveDate::from_ymd_opt(to.year(), 1, 1).expect("First day of year"));
+    if from > to {
+        return Err(anyhow!(
+            "The period starts after it ends: {} - {}",
+            from,
+            to
+        ));
+    }
+    let tree = AccountTree::load(&mut connection)?;
+    let q = args.account.build(args.limit, matcher);
+    let matching = q.execute(&mut connection)?;
+    let accounts: Vec<&Account> = if q.has_filter() {
+        matching.iter().collect()
+    } else {
+        tree.top_level()
+            .iter()
+            .filter_map(|guid| tree.get(guid))
+            .collect()
+    };
+    let series = Series {
+        period: Period::new(from, to),
+        interval: args.interval,
+        value: args.value,
+        // the top level accounts alone rarely have splits
+        rollup: args.rollup || !q.has_filter(),
+        output: args.output,
+    };
+    let (names, rows) = series.compute(&mut connection, &tree, &accounts);
+    print!("{}", series.write(&names, &rows)?);
+    Ok(rows.len())
+}
+
+fn handle_reconciliation(args: ReconciliationArgs, matcher: TextMatcher) -> Result<usize> {
+    let mut connection = establish_connection();
+    let date = parse_date_arg("report", &args.date)?.unwrap_or_else(|| Local::now().date_naive());
+    let q = args.account.build(Some(i64::MAX), matcher);
+    let tree = AccountTree::load(&mut connection)?;
+    let roots = if q.has_filter() {
+        q.execute(&mut connection)?
+    } else {
+        Vec::new()
+    };
+    if q.has_filter() && roots.is_empty() {
+        return Err(anyhow!("No account matches: {}!", &q));
+    }
+    let summary = ReconciliationSummary {
+        date,
+        older_than_days: args.older_than,
+    };
+    Ok(summary.display(&mut connection, &tree, &roots.iter().collect::<Vec<_>>()))
+}
+
+fn handle_budget(args: BudgetArgs, matcher: TextMatcher) -> Result<usize> {
+    let mut connection = establish_connection();
+    let plan = BudgetPlan::load(&mut connection, &args.name, matcher)?;
+    let tree = AccountTree::load(&mut connection)?;
+    let periods: Vec<i32> = if args.all_periods {
+        (0..plan.budget.num_periods).collect()
+    } else if let Some(period) = args.period {
+        if period < 1 || period > plan.budget.num_periods {
+            return Err(anyhow!(
+                "The budget has periods from 1 to {}!",
+                plan.budget.num_periods
+            ));
+        }
+        vec![period - 1]
+    } else {
+        let date =
+            parse_date_arg("budget", &args.date)?.unwrap_or_else(|| Local::now().date_naive());
+        let period = plan
+            .period_at(date)?
+            .with_context(|| format!("The budget doesn't cover {}!", date))?;
+        vec![period]
+    };
+    let mut count = 0;
+    for period in periods {
+        count += plan.display_period(&mut connection, &tree, period)?;
+        println!();
+    }
+    Ok(count)
+}
+
+fn handle_list_entries(args: TransactionsArgs, matcher: TextMatcher) -> Result<usize> {
     let term = Term::stdout();
 
     let mut connection = establish_connection();
-    let account_query = args.account.build(None);
+    // every matching account is listed, not just the first few
+    let account_query = args.account.build(Some(i64::MAX), matcher);
     let move_target_account = if args.move_split {
-        let target_account_query = args.target_account.build(None);
-        let target_account = target_account_query.get_one(&mut connection, false);
+        let target_account_query = args.target_account.build(None, matcher);
+        let target_account = target_account_query.get_one(&mut connection, false)?;
         if target_account.is_none() {
             term.write_line(&format!(
                 "Unable to determine the target account for the move-split command:{:?}",
@@ -93,61 +288,113 @@ fn handle_list_entries(args: TransactionsArgs) -> Result<usize> {
     } else {
         None
     };
-    let q = if let Some(account) = account_query.get_one(&mut connection, false) {
+    let q = if account_query.has_filter() {
+        let matching = account_query.execute(&mut connection)?;
+        if matching.is_empty() {
+            return Err(anyhow!("No account matches: {}!", &account_query));
+        }
+        let tree = AccountTree::load(&mut connection)?;
+        let accounts: Vec<&Account> = if args.include_children {
+            let roots: Vec<&Account> = matching.iter().collect();
+            tree.walk(&roots, None)
+                .into_iter()
+                .map(|(account, _)| account)
+                .collect()
+        } else {
+            matching.iter().collect()
+        };
+        if move_target_account.is_some() && accounts.len() != 1 {
+            let names: Vec<String> = accounts
+                .iter()
+                .map(|account| tree.full_name(&account.guid).unwrap_or_default())
+                .collect();
+            return Err(anyhow!(
+                "The move-split command needs exactly one source account, found: [{}]",
+                names.join(", ")
+            ));
+        }
         if let Some(target_account) = &move_target_account {
-            if target_account.commodity_guid != account.commodity_guid {
-                term.write_line(&format!(
-                    "The two account has different commodities, unable to transfer between: {} - {}",
-                    style(&account).red(),
-                    style(target_account).red()
-                ))?;
-                return Err(anyhow!(
-                    "Different commodities: from account={} target account={}!",
-                    &account,
-                    target_account
-                ));
+            for account in &accounts {
+                if target_account.commodity_guid != account.commodity_guid {
+                    term.write_line(&format!(
+                        "The two account has different commodities, unable to transfer between: {} - {}",
+                        style(&account).red(),
+                        style(target_account).red()
+                    ))?;
+                    return Err(anyhow!(
+                        "Different commodities: from account={} target account={}!",
+                        &account,
+                        target_account
+                    ));
+                }
             }
         }
 
+        let names: Vec<&str> = accounts
+            .iter()
+            .map(|account| account.name.as_str())
+            .collect();
         term.write_line(&format!(
             "Listing transactions in {}",
-            style(account.name).blue()
+            style(names.join(", ")).blue()
         ))?;
-        TransactionQuery::from(args).with_account_id(account.guid)
+        TransactionQuery::try_from(args)?
+            .with_matcher(matcher)
+            .with_account_ids(
+                accounts
+                    .iter()
+                    .map(|account| account.guid.clone())
+                    .collect(),
+            )
     } else {
         term.write_line("Listing transactions")?;
-        TransactionQuery::from(args)
+        TransactionQuery::try_from(args)?.with_matcher(matcher)
     };
     // term.write_line(&format!("Limit is {}", style(q.limit).red()))?;
     q.execute_and_process(&mut connection, &move_target_account, &term)
 }
 
-fn handle_commodities(cmd: CommoditiesArgs) -> Result<usize> {
+fn handle_commodities(cmd: CommoditiesArgs, matcher: TextMatcher) -> Result<usize> {
     let mut connection = establish_connection();
-    let q = CommoditiesQuery::from(cmd);
+    let q = CommoditiesQuery::from(cmd).with_matcher(matcher);
     q.execute_and_display(&mut connection)
 }
 
-fn handle_correlate(cmd: CorrelateArgs) -> Result<usize> {
+fn handle_correlate(cmd: CorrelateArgs, matcher: TextMatcher) -> Result<usize> {
     let format = cmd.format;
 
     let mut connection = establish_connection();
-    let matching = if cmd.by_booking_date {
-        Matching::ByBooking
+    let match_dates = if cmd.by_booking_date {
+        Some(DateOrder(vec![DateKind::Booking, DateKind::Value]))
     } else {
-        Matching::BySpending
+        cmd.match_dates.map(DateOrder)
     };
 
     let term = Term::stdout();
     let mut cmd = CorrelationCommand {
-        input_file: cmd.input,
-        sheet_name: cmd.sheet_name,
-        matching,
+        input: InputSource {
+            input_files: expand_input_files(&cmd.input)?,
+            sheet_name: cmd.sheet_name,
+            all_sheets: cmd.all_sheets,
+            strict: cmd.strict,
+            date_formats: cmd.date_formats,
+            decimal_separator: cmd.decimal_separator,
+            textual_date_patterns: cmd.textual_date_patterns,
+            normalize: NormalizerOverrides {
+                steps: cmd.normalize,
+                name_steps: cmd.normalize_names,
+                removals: cmd.remove_patterns,
+                aliases: cmd.payee_aliases,
+            },
+        },
+        match_dates,
+        post_dates: cmd.post_dates.map(DateOrder),
         verbose: cmd.verbose,
         list_extra_transactions: cmd.list_extra_transactions,
-        account_query: cmd.account.build(None),
-        counterparty_account_query: cmd.from_account.build(None),
-        fee_account_query: cmd.fee_account.build(None),
+        store_prices: cmd.store_prices,
+        account_query: cmd.account.build(None, matcher),
+        counterparty_account_query: cmd.from_account.build(None, matcher),
+        fee_account_query: cmd.fee_account.build(None, matcher),
     };
     let format = create_format(&format)
         .with_context(|| format!("Unknown format:'{}'!", format.unwrap_or_default()))?;
@@ -23,6 +23,13 @@ pub struct Account {
     pub placeholder: Option<i32>,
 }
 
+#[derive(Queryable, Debug)]
+pub struct Book {
+    pub guid: String,
+    pub root_account_guid: String,
+    pub root_template_guid: String,
+}
+
 #[derive(Queryable, Selectable, Debug, PartialEq)]
 #[diesel(table_name = splits)]
 #[diesel(belongs_to(Transaction))]
@@ -53,6 +60,34 @@ pub struct Transaction {
     pub description: Option<String>,
 }
 
+#[derive(Queryable, Debug)]
+pub struct Budget {
+    pub guid: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub num_periods: i32,
+}
+
+#[derive(Queryable, Debug)]
+pub struct BudgetAmount {
+    pub id: i32,
+    pub budget_guid: String,
+    pub account_guid: String,
+    pub period_num: i32,
+    pub amount_num: i64,
+    pub amount_denom: i64,
+}
+
+#[derive(Queryable, Debug)]
+pub struct Recurrence {
+    pub id: i32,
+    pub obj_guid: String,
+    pub recurrence_mult: i32,
+    pub recurrence_period_type: String,
+    pub recurrence_period_start: String,
+    pub recurrence_weekend_adjust: String,
+}
+
 #[derive(Queryable, Debug)]
 pub struct Commodities {
     pub guid: String,
@@ -67,6 +102,14 @@ pub struct Commodities {
 }
 
 impl Account {
+    // GnuCash shows the balance of these accounts with reversed sign, so incomes and debts are positive
+    pub fn is_credit_account(&self) -> bool {
+        matches!(
+            self.account_type.as_str(),
+            "INCOME" | "LIABILITY" | "CREDIT" | "EQUITY" | "PAYABLE"
+        )
+    }
+
     pub fn display(&self) {
         println!(
             "[{}]<id= {}>(parent= {},commodity= {}) - {} {}",
@@ -87,6 +130,15 @@ impl fmt::Display for Account {
 }
 
 impl Split {
+    // The amount in the commodity of the account
+    pub fn quantity(&self) -> f64 {
+        if self.quantity_denom == 0 {
+            0.0
+        } else {
+            self.quantity_num as f64 / self.quantity_denom as f64
+        }
+    }
+
     pub fn is_equal_amount(&self, amount: f64) -> bool {
         (amount * (self.quantity_denom as f64)) as i64 == self.quantity_num
     }
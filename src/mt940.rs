@@ -0,0 +1,371 @@
+use anyhow::Result;
+use chrono::{Datelike, NaiveDate};
+use regex::Regex;
+
+use crate::external_models::{
+    DateKind, DateOrder, ExternalTransaction, IssueKind, RowIssue, Statement, StatementBalance,
+    StatementFormat,
+};
+
+// SWIFT MT940 customer statement, with the structured :86: field used by German and Austrian banks.
+pub struct Mt940Format;
+
+struct Field {
+    line: usize,
+    tag: String,
+    content: String,
+}
+
+#[derive(Default, Debug, PartialEq)]
+struct Details {
+    posting_text: Option<String>,
+    purpose: Vec<String>,
+    counterparty_account: Option<String>,
+    counterparty_name: Option<String>,
+}
+
+impl StatementFormat for Mt940Format {
+    // The purpose often mentions other dates, like the date of the invoice, so only the
+    // dates of the bank are used, the booking date first, as the balances follow that.
+    fn date_order(&self) -> DateOrder {
+        DateOrder(vec![DateKind::Booking, DateKind::Value])
+    }
+
+    fn parse_statement(&self, content: &str) -> Result<Statement> {
+        let mut statement = Statement::default();
+        let mut current: Option<ExternalTransaction> = None;
+        for field in split_fields(content) {
+            match field.tag.as_str() {
+                "60F" | "60M" => match parse_balance(&field.content) {
+                    Ok(balance) => {
+                        if statement.opening_balance.is_none() {
+                            statement.opening_balance = Some(balance);
+                        }
+                    }
+                    Err(err) => statement.issues.push(rejected(&field, err)),
+                },
+                "62F" | "62M" => match parse_balance(&field.content) {
+                    Ok(balance) => statement.closing_balance = Some(balance),
+                    Err(err) => statement.issues.push(rejected(&field, err)),
+                },
+                "61" => {
+                    if let Some(finished) = current.take() {
+                        statement.transactions.push(finished);
+                    }
+                    match parse_statement_line(&field.content) {
+                        Ok(transaction) => current = Some(transaction),
+                        Err(err) => statement.issues.push(rejected(&field, err)),
+                    }
+                }
+                "86" => {
+                    if let Some(transaction) = current.as_mut() {
+                        apply_details(transaction, &field.content);
+                    }
+                }
+                _ => {
+                    if let Some(finished) = current.take() {
+                        statement.transactions.push(finished);
+                    }
+                }
+            }
+        }
+        if let Some(finished) = current.take() {
+            statement.transactions.push(finished);
+        }
+        Ok(statement)
+    }
+}
+
+fn rejected(field: &Field, err: anyhow::Error) -> RowIssue {
+    RowIssue {
+        row: field.line,
+        kind: IssueKind::Rejected,
+        reason: err.to_string(),
+    }
+}
+
+fn split_fields(content: &str) -> Vec<Field> {
+    lazy_static! {
+        static ref TAG: Regex = Regex::new(r"^:([0-9]{2}[A-Z]?):(.*)$").unwrap();
+    }
+    let mut fields: Vec<Field> = Vec::new();
+    for (idx, line) in content.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if let Some(caps) = TAG.captures(line) {
+            fields.push(Field {
+                line: idx + 1,
+                tag: caps[1].to_owned(),
+                content: caps[2].to_owned(),
+            });
+        } else if line == "-" || line.starts_with('{') {
+            // end of message or SWIFT header block
+            fields.push(Field {
+                line: idx + 1,
+                tag: String::new(),
+                content: String::new(),
+            });
+        } else if let Some(last) = fields.last_mut() {
+            last.content.push('\n');
+            last.content.push_str(line);
+        }
+    }
+    fields
+}
+
+fn parse_amount(value: &str) -> Option<f64> {
+    value.replace(',', ".").parse().ok()
+}
+
+fn parse_short_date(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value, "%y%m%d").ok()
+}
+
+// :60F:C230101EUR1000,00
+fn parse_balance(content: &str) -> Result<StatementBalance> {
+    lazy_static! {
+        static ref BALANCE: Regex = Regex::new(r"^([CD])([0-9]{6})([A-Z]{3})([0-9,]+)").unwrap();
+    }
+    let caps = BALANCE
+        .captures(content.trim())
+        .ok_or_else(|| anyhow!("Invalid balance field: '{}'", content))?;
+    let amount =
+        parse_amount(&caps[4]).ok_or_else(|| anyhow!("Invalid balance amount: '{}'", content))?;
+    Ok(StatementBalance {
+        date: parse_short_date(&caps[2]),
+        amount: if &caps[1] == "D" { -amount } else { amount },
+    })
+}
+
+// The entry date has no year, it is the one closest to the value date.
+fn infer_booking_date(value_date: NaiveDate, month_day: &str) -> Option<NaiveDate> {
+    let month = month_day.get(0..2)?.parse().ok()?;
+    let day = month_day.get(2..4)?.parse().ok()?;
+    [
+        value_date.year() - 1,
+        value_date.year(),
+        value_date.year() + 1,
+    ]
+    .iter()
+    .filter_map(|year| NaiveDate::from_ymd_opt(*year, month, day))
+    .min_by_key(|date| (*date - value_date).num_days().abs())
+}
+
+// :61:2301100111DR135,50NTRFNONREF//BANKREF
+fn parse_statement_line(content: &str) -> Result<ExternalTransaction> {
+    lazy_static! {
+        static ref LINE: Regex = Regex::new(
+            r"^([0-9]{6})([0-9]{4})?(R?[CD])([A-Z])?([0-9,]+)([NSF][A-Z0-9]{3})([^\n]*?)(?://([^\n]*))?(?:\n([\s\S]*))?$"
+        )
+        .unwrap();
+    }
+    let caps = LINE
+        .captures(content.trim())
+        .ok_or_else(|| anyhow!("Invalid statement line: '{}'", content))?;
+    let value_date = parse_short_date(&caps[1]);
+    let booking_date = match (value_date, caps.get(2)) {
+        (Some(date), Some(month_day)) => infer_booking_date(date, month_day.as_str()),
+        _ => value_date,
+    };
+    let amount = parse_amount(&caps[5])
+        .ok_or_else(|| anyhow!("Invalid amount in statement line: '{}'", content))?;
+    // Debit and the reversal of a credit decrease the balance
+    let amount = match &caps[3] {
+        "D" | "RC" => -amount,
+        _ => amount,
+    };
+    let customer_reference = caps
+        .get(7)
+        .map(|m| m.as_str().trim())
+        .filter(|r| !r.is_empty() && *r != "NONREF");
+    let bank_reference = caps
+        .get(8)
+        .map(|m| m.as_str().trim())
+        .filter(|r| !r.is_empty());
+    Ok(ExternalTransaction {
+        date: value_date,
+        booking_date,
+        amount: Some(amount),
+        category: None,
+        description: caps.get(9).map(|m| m.as_str().trim().to_owned()),
+        other_account: None,
+        other_account_name: None,
+        textual_date: None,
+        transaction_fee: None,
+        reference: bank_reference.or(customer_reference).map(str::to_owned),
+        ..Default::default()
+    })
+}
+
+fn apply_details(transaction: &mut ExternalTransaction, content: &str) {
+    match parse_details(content) {
+        Some(details) => {
+            let purpose = details.purpose.join("");
+            let sepa = split_sepa_purpose(&purpose);
+            let description = sepa
+                .iter()
+                .find(|(key, _)| key == "SVWZ")
+                .map(|(_, text)| text.clone())
+                .or_else(|| Some(purpose.clone()).filter(|p| sepa.is_empty() && !p.is_empty()));
+            if transaction.reference.is_none() {
+                transaction.reference = sepa
+                    .iter()
+                    .find(|(key, _)| key == "EREF")
+                    .map(|(_, text)| text.clone())
+                    .filter(|r| r != "NOTPROVIDED");
+            }
+            transaction.category = details.posting_text;
+            transaction.description = description.or_else(|| transaction.description.take());
+            transaction.other_account = details.counterparty_account;
+            transaction.other_account_name = details.counterparty_name;
+        }
+        None => {
+            let text = content.replace('\n', " ").trim().to_owned();
+            if !text.is_empty() {
+                transaction.description = Some(text);
+            }
+        }
+    }
+}
+
+// Structured content looks like '166?00SEPA-UEBERWEISUNG?20EREF+...?32Name'
+fn parse_details(content: &str) -> Option<Details> {
+    let joined = content.replace(['\n', '\r'], "");
+    let mut chars = joined.chars();
+    let code: String = chars.by_ref().take(3).collect();
+    let separator = chars.next()?;
+    if code.len() != 3 || !code.chars().all(|c| c.is_ascii_digit()) || separator.is_alphanumeric() {
+        return None;
+    }
+    let mut details = Details::default();
+    for subfield in chars.as_str().split(separator) {
+        if subfield.len() < 2 || !subfield.is_char_boundary(2) {
+            continue;
+        }
+        let (key, value) = subfield.split_at(2);
+        let value = value.trim();
+        match key {
+            "00" => details.posting_text = Some(value.to_owned()).filter(|v| !v.is_empty()),
+            "20" | "21" | "22" | "23" | "24" | "25" | "26" | "27" | "28" | "29" | "60" | "61"
+            | "62" | "63" => details.purpose.push(subfield[2..].to_owned()),
+            "31" => details.counterparty_account = Some(value.to_owned()).filter(|v| !v.is_empty()),
+            "32" | "33" if !value.is_empty() => {
+                details.counterparty_name = Some(match details.counterparty_name.take() {
+                    Some(name) => name + value,
+                    None => value.to_owned(),
+                });
+            }
+            _ => {}
+        }
+    }
+    Some(details)
+}
+
+// Splits SEPA purpose texts like 'EREF+123 SVWZ+Rent' into their keyed parts.
+fn split_sepa_purpose(purpose: &str) -> Vec<(String, String)> {
+    lazy_static! {
+        static ref KEY: Regex =
+            Regex::new(r"(EREF|KREF|MREF|CRED|DEBT|SVWZ|ABWA|ABWE|IBAN|BIC)\+").unwrap();
+    }
+    let keys: Vec<_> = KEY.captures_iter(purpose).collect();
+    keys.iter()
+        .enumerate()
+        .map(|(idx, caps)| {
+            let whole = caps.get(0).unwrap();
+            let end = keys
+                .get(idx + 1)
+                .map_or(purpose.len(), |next| next.get(0).unwrap().start());
+            (
+                caps[1].to_owned(),
+                purpose[whole.end()..end].trim().to_owned(),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::DateExtractor;
+
+    const SAMPLE: &str = ":20:STARTUMSE
+:25:12000000/0012345678
+:28C:00001/001
+:60F:C221231EUR1000,00
+:61:2301020102DR135,50NTRFNONREF//BANKREF-1
+:86:116?00SEPA-UEBERWEISUNG?109310?20EREF+E2E-1 SVWZ+Miete?21 Januar?30BKAUATWW
+?31AT611904300234573201?32Landlord
+:61:2212311231CR50,00NMSCNONREF
+:86:Interest payment
+:62F:C230102EUR914,50
+-";
+
+    #[test]
+    fn test_parse_mt940() {
+        let statement = Mt940Format {}.parse_statement(SAMPLE).unwrap();
+        assert_eq!(statement.transactions.len(), 2);
+
+        let rent = &statement.transactions[0];
+        assert_eq!(rent.date, NaiveDate::from_ymd_opt(2023, 1, 2));
+        assert_eq!(rent.booking_date, NaiveDate::from_ymd_opt(2023, 1, 2));
+        assert_eq!(rent.amount, Some(-135.5));
+        assert_eq!(rent.category.as_deref(), Some("SEPA-UEBERWEISUNG"));
+        assert_eq!(rent.description.as_deref(), Some("Miete Januar"));
+        assert_eq!(rent.other_account.as_deref(), Some("AT611904300234573201"));
+        assert_eq!(rent.other_account_name.as_deref(), Some("Landlord"));
+        assert_eq!(rent.reference.as_deref(), Some("BANKREF-1"));
+
+        let interest = &statement.transactions[1];
+        assert_eq!(interest.amount, Some(50.0));
+        assert_eq!(interest.description.as_deref(), Some("Interest payment"));
+        assert_eq!(interest.reference, None);
+
+        assert_eq!(statement.opening_balance.unwrap().amount, 1000.0);
+        assert_eq!(
+            statement.closing_balance.unwrap().date,
+            NaiveDate::from_ymd_opt(2023, 1, 2)
+        );
+    }
+
+    #[test]
+    fn test_dates_of_the_bank_are_preferred() {
+        let content = ":61:2301020103DR135,50NTRFNONREF\n:86:Invoice of 15.12.2022\n";
+        let format = Mt940Format {};
+        let mut statement = format.parse_statement(content).unwrap();
+        let dates = DateExtractor::new(format.textual_date_patterns()).unwrap();
+        statement.prepare(&dates, &format.normalizer());
+        let payment = &statement.transactions[0];
+        assert_eq!(payment.textual_date, NaiveDate::from_ymd_opt(2022, 12, 15));
+        assert_eq!(
+            payment.get_date_by(&format.date_order()),
+            NaiveDate::from_ymd_opt(2023, 1, 3)
+        );
+    }
+
+    #[test]
+    fn test_invalid_statement_line_is_reported() {
+        let content = ":20:STARTUMSE\n:61:230102XX135,50NTRFNONREF\n:86:Broken\n:61:2301030103CR1,00NMSCNONREF\n";
+        let statement = Mt940Format {}.parse_statement(content).unwrap();
+        assert_eq!(statement.transactions.len(), 1);
+        assert_eq!(statement.issues.len(), 1);
+        assert_eq!(statement.issues[0].row, 2);
+        assert_eq!(statement.issues[0].kind, IssueKind::Rejected);
+    }
+
+    #[test]
+    fn test_non_ascii_digits_are_rejected() {
+        // the entry date with Arabic-Indic digits
+        assert!(parse_statement_line("230110٠١١٠DR135,50NTRFNONREF").is_err());
+        assert_eq!(
+            infer_booking_date(NaiveDate::from_ymd_opt(2023, 1, 10).unwrap(), "٠١١٠"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_booking_date_over_year_end() {
+        assert_eq!(
+            infer_booking_date(NaiveDate::from_ymd_opt(2022, 12, 31).unwrap(), "0102"),
+            NaiveDate::from_ymd_opt(2023, 1, 2)
+        );
+    }
+}
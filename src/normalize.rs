@@ -0,0 +1,266 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use regex::Regex;
+
+use crate::external_models::ExternalTransaction;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum NormalizeStep {
+    // Lowercase the texts which are written in all capitals
+    Casefold,
+    // Repair accents written as A' or O: by some Hungarian banks
+    Accents,
+    // Trim and collapse the repeated whitespaces
+    Whitespace,
+}
+
+// The enabled steps for one kind of text
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TextSteps {
+    pub case_fold: bool,
+    pub accent_repair: bool,
+    pub collapse_whitespace: bool,
+}
+
+impl TextSteps {
+    pub fn new(steps: &[NormalizeStep]) -> Self {
+        TextSteps {
+            case_fold: steps.contains(&NormalizeStep::Casefold),
+            accent_repair: steps.contains(&NormalizeStep::Accents),
+            collapse_whitespace: steps.contains(&NormalizeStep::Whitespace),
+        }
+    }
+}
+
+// Cleans up the imported descriptions and counterparty names. The steps always
+// run in the same order: case folding, accent repair, removals, whitespace
+// collapsing, and finally payee aliasing.
+#[derive(Clone, Debug)]
+pub struct Normalizer {
+    pub name_steps: TextSteps,
+    pub description_steps: TextSteps,
+    pub removals: Vec<Regex>,
+    pub aliases: Vec<(Regex, String)>,
+}
+
+impl Default for Normalizer {
+    fn default() -> Self {
+        Normalizer::with_steps(&[NormalizeStep::Whitespace])
+    }
+}
+
+impl Normalizer {
+    // The same steps for the names and the descriptions
+    pub fn with_steps(steps: &[NormalizeStep]) -> Self {
+        Normalizer {
+            name_steps: TextSteps::new(steps),
+            description_steps: TextSteps::new(steps),
+            removals: Vec::new(),
+            aliases: Vec::new(),
+        }
+    }
+
+    pub fn with_name_steps(self, steps: &[NormalizeStep]) -> Self {
+        Normalizer {
+            name_steps: TextSteps::new(steps),
+            ..self
+        }
+    }
+
+    pub fn normalize(&self, steps: TextSteps, input: &str) -> String {
+        let mut result = if steps.case_fold && input.to_uppercase() == input {
+            input.to_lowercase()
+        } else {
+            input.to_owned()
+        };
+        if steps.accent_repair {
+            result = repair_accents(&result);
+        }
+        for removal in &self.removals {
+            result = removal.replace_all(&result, "").into_owned();
+        }
+        if steps.collapse_whitespace {
+            result = result.split_whitespace().collect::<Vec<_>>().join(" ");
+        }
+        result
+    }
+
+    // Descriptions often start with the counterparty name, that part is cleaned like the name
+    fn normalize_description(&self, description: &str, name: Option<&str>) -> String {
+        match name.and_then(|name| Some((name, description.strip_prefix(name)?))) {
+            Some((name, rest)) if self.name_steps != self.description_steps => {
+                let name = self.normalize(self.name_steps, name);
+                let rest = self.normalize(self.description_steps, rest);
+                match (name.is_empty(), rest.is_empty()) {
+                    (_, true) => name,
+                    (true, false) => rest,
+                    (false, false) => format!("{} {}", name, rest.trim_start()),
+                }
+            }
+            _ => self.normalize(self.description_steps, description),
+        }
+    }
+
+    fn find_alias(&self, text: &str) -> Option<String> {
+        self.aliases
+            .iter()
+            .find(|(pattern, _)| pattern.is_match(text))
+            .map(|(_, alias)| alias.clone())
+    }
+
+    // The alias replaces the counterparty name, or provides one from the description,
+    // the description itself is kept.
+    pub fn apply(&self, transaction: &mut ExternalTransaction) {
+        let name = transaction.other_account_name.as_deref();
+        transaction.description = transaction
+            .description
+            .as_ref()
+            .map(|text| self.normalize_description(text, name))
+            .filter(|text| !text.is_empty());
+        transaction.other_account_name = transaction
+            .other_account_name
+            .as_ref()
+            .map(|text| self.normalize(self.name_steps, text))
+            .filter(|text| !text.is_empty());
+        let alias = transaction
+            .other_account_name
+            .as_ref()
+            .or(transaction.description.as_ref())
+            .and_then(|text| self.find_alias(text));
+        if alias.is_some() {
+            transaction.other_account_name = alias;
+        }
+    }
+}
+
+fn repair_accents(input: &str) -> String {
+    input
+        .replace("A'", "Á")
+        .replace("I'", "Í")
+        .replace("E'", "É")
+        .replace("O'", "Ó")
+        .replace("U'", "Ú")
+        .replace("U:", "Ü")
+        .replace("O:", "Ö")
+        .replace("a'", "á")
+        .replace("i'", "í")
+        .replace("e'", "é")
+        .replace("o'", "ó")
+        .replace("u'", "ú")
+        .replace("u:", "ü")
+        .replace("o:", "ö")
+}
+
+// Normalization settings from the command line, applied on top of the format's own.
+#[derive(Default)]
+pub struct NormalizerOverrides {
+    pub steps: Option<Vec<NormalizeStep>>,
+    // The steps of the counterparty names, the same as the other steps by default
+    pub name_steps: Option<Vec<NormalizeStep>>,
+    pub removals: Vec<String>,
+    pub aliases: Vec<String>,
+}
+
+impl NormalizerOverrides {
+    pub fn apply(&self, base: Normalizer) -> Result<Normalizer> {
+        let mut normalizer = match &self.steps {
+            Some(steps) => Normalizer {
+                removals: base.removals,
+                aliases: base.aliases,
+                ..Normalizer::with_steps(steps)
+            },
+            None => base,
+        };
+        if let Some(steps) = &self.name_steps {
+            normalizer = normalizer.with_name_steps(steps);
+        }
+        for removal in &self.removals {
+            normalizer.removals.push(
+                Regex::new(removal)
+                    .with_context(|| format!("Invalid removal pattern: '{}'", removal))?,
+            );
+        }
+        for alias in &self.aliases {
+            let (pattern, name) = alias
+                .split_once('=')
+                .with_context(|| format!("Alias should be PATTERN=NAME: '{}'", alias))?;
+            let regex = Regex::new(pattern)
+                .with_context(|| format!("Invalid alias pattern: '{}'", pattern))?;
+            normalizer.aliases.push((regex, name.to_owned()));
+        }
+        Ok(normalizer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accent_repair_and_case_fold() {
+        let steps = TextSteps::new(&[NormalizeStep::Casefold, NormalizeStep::Accents]);
+        let normalizer = Normalizer::default();
+        assert_eq!(normalizer.normalize(steps, "KO:RTE'FA"), "körtéfa");
+        assert_eq!(normalizer.normalize(steps, "Ko:rte'fa Kft"), "Körtéfa Kft");
+    }
+
+    #[test]
+    fn test_name_steps_can_be_overridden() {
+        let base = Normalizer::default()
+            .with_name_steps(&[NormalizeStep::Casefold, NormalizeStep::Whitespace]);
+        let transaction = || ExternalTransaction {
+            description: Some("SPAR MAGYARORSZAG Receipt 12".to_owned()),
+            other_account_name: Some("SPAR MAGYARORSZAG".to_owned()),
+            ..Default::default()
+        };
+        let mut folded = transaction();
+        base.apply(&mut folded);
+        assert_eq!(
+            folded.other_account_name.as_deref(),
+            Some("spar magyarorszag")
+        );
+        assert_eq!(
+            folded.description.as_deref(),
+            Some("spar magyarorszag Receipt 12")
+        );
+
+        let overrides = NormalizerOverrides {
+            name_steps: Some(vec![NormalizeStep::Whitespace]),
+            ..Default::default()
+        };
+        let mut kept = transaction();
+        overrides.apply(base).unwrap().apply(&mut kept);
+        assert_eq!(
+            kept.other_account_name.as_deref(),
+            Some("SPAR MAGYARORSZAG")
+        );
+        assert_eq!(kept.description, transaction().description);
+    }
+
+    #[test]
+    fn test_removal_and_alias() {
+        let overrides = NormalizerOverrides {
+            steps: None,
+            name_steps: None,
+            removals: vec![r"\*{4}\d{4}".to_owned()],
+            aliases: vec!["(?i)^tesco=Tesco".to_owned()],
+        };
+        let normalizer = overrides.apply(Normalizer::default()).unwrap();
+        let mut transaction = ExternalTransaction {
+            description: Some("TESCO  BUDAORS ****1683  ".to_owned()),
+            ..Default::default()
+        };
+        normalizer.apply(&mut transaction);
+        assert_eq!(transaction.description.as_deref(), Some("TESCO BUDAORS"));
+        assert_eq!(transaction.other_account_name.as_deref(), Some("Tesco"));
+    }
+
+    #[test]
+    fn test_invalid_alias() {
+        let overrides = NormalizerOverrides {
+            aliases: vec!["tesco".to_owned()],
+            ..Default::default()
+        };
+        assert!(overrides.apply(Normalizer::default()).is_err());
+    }
+}
@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use console::style;
+use diesel::prelude::*;
+
+use crate::models::{Account, Book};
+
+// Every account of the book, linked by their parent guid. The scheduled transaction
+// templates are under a separate root, so they are not reachable from the book root.
+pub struct AccountTree {
+    root_guid: String,
+    accounts: HashMap<String, Account>,
+    children: HashMap<String, Vec<String>>,
+    // commodity guid -> (mnemonic, fraction)
+    commodities: HashMap<String, (String, i32)>,
+}
+
+impl AccountTree {
+    pub fn load(connection: &mut SqliteConnection) -> Result<Self> {
+        use crate::schema::{accounts, books, commodities};
+
+        let book = books::table
+            .first::<Book>(connection)
+            .optional()
+            .expect("Error loading the book")
+            .ok_or_else(|| anyhow!("The database has no book!"))?;
+        let accounts: HashMap<String, Account> = accounts::table
+            .load::<Account>(connection)
+            .expect("Error loading accounts")
+            .into_iter()
+            .map(|account| (account.guid.clone(), account))
+            .collect();
+        let commodities = commodities::table
+            .select((
+                commodities::guid,
+                commodities::mnemonic,
+                commodities::fraction,
+            ))
+            .load::<(String, String, i32)>(connection)
+            .expect("Error loading commodities")
+            .into_iter()
+            .map(|(guid, mnemonic, fraction)| (guid, (mnemonic, fraction)))
+            .collect();
+
+        Ok(AccountTree::new(
+            book.root_account_guid,
+            accounts,
+            commodities,
+        ))
+    }
+
+    pub(crate) fn new(
+        root_guid: String,
+        accounts: HashMap<String, Account>,
+        commodities: HashMap<String, (String, i32)>,
+    ) -> Self {
+        let mut children: HashMap<String, Vec<String>> = HashMap::new();
+        for account in accounts.values() {
+            if let Some(parent) = &account.parent_guid {
+                children
+                    .entry(parent.clone())
+                    .or_default()
+                    .push(account.guid.clone());
+            }
+        }
+        for list in children.values_mut() {
+            list.sort_by(|a, b| accounts[a].name.cmp(&accounts[b].name));
+        }
+        AccountTree {
+            root_guid,
+            accounts,
+            children,
+            commodities,
+        }
+    }
+
+    pub fn get(&self, guid: &str) -> Option<&Account> {
+        self.accounts.get(guid)
+    }
+
+    // Resolves a full name, like 'Expenses:Food:Groceries', walking down from the book root
+    pub fn find_by_path(&self, full_name: &str) -> Option<&Account> {
+        let mut current = self.accounts.get(&self.root_guid)?;
+        for name in full_name.split(':') {
+            current = self
+                .children(&current.guid)
+                .iter()
+                .map(|guid| &self.accounts[guid])
+                .find(|account| account.name == name)?;
+        }
+        Some(current)
+    }
+
+    // The ancestors of the account, without the book root, starting with the top level one.
+    // None, if the account is not under the book root.
+    fn path(&self, guid: &str) -> Option<Vec<&Account>> {
+        let mut result = Vec::new();
+        let mut current = self.accounts.get(guid)?;
+        while current.guid != self.root_guid {
+            result.push(current);
+            current = self.accounts.get(current.parent_guid.as_ref()?)?;
+        }
+        result.reverse();
+        Some(result)
+    }
+
+    pub fn full_name(&self, guid: &str) -> Option<String> {
+        self.path(guid).map(|path| {
+            path.iter()
+                .map(|account| account.name.as_str())
+                .collect::<Vec<_>>()
+                .join(":")
+        })
+    }
+
+    // The level of the account, 1 for the top level accounts
+    pub fn depth(&self, guid: &str) -> Option<usize> {
+        self.path(guid).map(|path| path.len())
+    }
+
+    pub fn commodity(&self, account: &Account) -> &str {
+        account
+            .commodity_guid
+            .as_ref()
+            .and_then(|guid| self.commodities.get(guid))
+            .map_or("", |(mnemonic, _)| mnemonic.as_str())
+    }
+
+    // The amount with as many decimals, as the smallest unit of the account's commodity
+    pub fn format_amount(&self, account: &Account, amount: f64) -> String {
+        let fraction = account
+            .commodity_guid
+            .as_ref()
+            .and_then(|guid| self.commodities.get(guid))
+            .map_or(100, |(_, fraction)| *fraction);
+        let decimals = (fraction.max(1) as f64).log10().round() as usize;
+        format!("{:.*} {}", decimals, amount, self.commodity(account))
+    }
+
+    pub fn children(&self, guid: &str) -> &[String] {
+        self.children.get(guid).map_or(&[], |list| list.as_slice())
+    }
+
+    pub fn top_level(&self) -> &[String] {
+        self.children(&self.root_guid)
+    }
+
+    // The account and every account under it, parents before their children
+    pub fn subtree(&self, guid: &str) -> Vec<&Account> {
+        let mut result = Vec::new();
+        self.collect_subtree(guid, &mut result);
+        result
+    }
+
+    fn collect_subtree<'a>(&'a self, guid: &str, result: &mut Vec<&'a Account>) {
+        if let Some(account) = self.accounts.get(guid) {
+            result.push(account);
+            for child in self.children(guid) {
+                self.collect_subtree(child, result);
+            }
+        }
+    }
+
+    // The given accounts with their subtrees with the level of each account, or the whole
+    // book, when no account is given. Parents are listed before their children.
+    pub fn walk(&self, roots: &[&Account], max_depth: Option<usize>) -> Vec<(&Account, usize)> {
+        let root_guids: Vec<&String> = if roots.is_empty() {
+            self.top_level().iter().collect()
+        } else {
+            let guids: Vec<&String> = roots.iter().map(|account| &account.guid).collect();
+            // the accounts under an other matching account are shown in its subtree
+            guids
+                .iter()
+                .filter(|guid| {
+                    self.path(guid).is_some_and(|path| {
+                        path.iter()
+                            .rev()
+                            .skip(1)
+                            .all(|ancestor| !guids.contains(&&ancestor.guid))
+                    })
+                })
+                .copied()
+                .collect()
+        };
+        root_guids
+            .into_iter()
+            .flat_map(|guid| self.subtree(guid))
+            .map(|account| (account, self.depth(&account.guid).unwrap_or_default()))
+            .filter(|(_, depth)| max_depth.is_none_or(|max| *depth <= max))
+            .collect()
+    }
+
+    pub fn display(&self, roots: &[&Account], max_depth: Option<usize>) -> usize {
+        let accounts = self.walk(roots, max_depth);
+        for (account, depth) in &accounts {
+            println!("{}", self.describe(account, *depth));
+        }
+        accounts.len()
+    }
+
+    fn describe(&self, account: &Account, depth: usize) -> String {
+        let mut line = format!(
+            "{}{} [{}] {}",
+            "  ".repeat(depth.saturating_sub(1)),
+            style(self.full_name(&account.guid).unwrap_or_default()).cyan(),
+            account.account_type,
+            self.commodity(account)
+        );
+        if account.hidden.unwrap_or_default() != 0 {
+            line.push_str(&format!(" {}", style("(hidden)").dim()));
+        }
+        if account.placeholder.unwrap_or_default() != 0 {
+            line.push_str(&format!(" {}", style("(placeholder)").yellow()));
+        }
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account(guid: &str, name: &str, parent: Option<&str>) -> (String, Account) {
+        let account = Account {
+            guid: guid.to_owned(),
+            name: name.to_owned(),
+            account_type: "EXPENSE".to_owned(),
+            commodity_guid: None,
+            commodity_scu: 100,
+            non_std_scu: 0,
+            parent_guid: parent.map(|p| p.to_owned()),
+            code: None,
+            description: None,
+            hidden: None,
+            placeholder: None,
+        };
+        (guid.to_owned(), account)
+    }
+
+    #[test]
+    fn test_find_by_path() {
+        let accounts = vec![
+            account("root", "Root Account", None),
+            account("template", "Template Root", None),
+            account("exp", "Expenses", Some("root")),
+            account("food", "Food", Some("exp")),
+            account("groceries", "Groceries", Some("food")),
+            account("seafood", "Groceries", Some("template")),
+        ];
+        let tree = AccountTree::new(
+            "root".to_owned(),
+            accounts.into_iter().collect(),
+            HashMap::new(),
+        );
+        let groceries = tree.find_by_path("Expenses:Food:Groceries");
+        assert_eq!(groceries.map(|a| a.guid.as_str()), Some("groceries"));
+        assert_eq!(
+            tree.full_name("groceries").as_deref(),
+            Some("Expenses:Food:Groceries")
+        );
+        assert!(tree.find_by_path("Expenses:Groceries").is_none());
+        assert!(tree.find_by_path("Groceries").is_none());
+        assert_eq!(tree.full_name("seafood"), None);
+    }
+}
@@ -1,10 +1,13 @@
 use std::fmt;
 
+use anyhow::Result;
 use diesel::prelude::*;
 
 use crate::{
     cli::{DefaultAccountParams, FeeAccountParams, FromAccountParams, TargetAccountParams},
     models::Account,
+    query::account_tree::AccountTree,
+    query::text_match::{filter_text, TextMatcher},
 };
 
 #[derive(Debug)]
@@ -15,14 +18,17 @@ pub struct AccountQuery {
     pub parent_filter: Option<String>,
     pub type_filter: Option<String>,
     pub parent_name_filter: Option<String>,
+    // Exact full name, like 'Expenses:Food:Groceries'
+    pub path_filter: Option<String>,
+    pub matcher: TextMatcher,
 }
 
 pub(crate) trait ToAccountQuery {
-    fn build(&self, limit: Option<i64>) -> AccountQuery;
+    fn build(&self, limit: Option<i64>, matcher: TextMatcher) -> AccountQuery;
 }
 
 impl ToAccountQuery for DefaultAccountParams {
-    fn build(&self, limit: Option<i64>) -> AccountQuery {
+    fn build(&self, limit: Option<i64>, matcher: TextMatcher) -> AccountQuery {
         AccountQuery {
             limit: limit.unwrap_or(10),
             guid_filter: self.guid.clone(),
@@ -30,12 +36,14 @@ impl ToAccountQuery for DefaultAccountParams {
             parent_filter: self.parent_guid.clone(),
             type_filter: self.account_type.clone(),
             parent_name_filter: self.parent_name.clone(),
+            path_filter: self.path.clone(),
+            matcher,
         }
     }
 }
 
 impl ToAccountQuery for TargetAccountParams {
-    fn build(&self, limit: Option<i64>) -> AccountQuery {
+    fn build(&self, limit: Option<i64>, matcher: TextMatcher) -> AccountQuery {
         AccountQuery {
             limit: limit.unwrap_or(10),
             guid_filter: self.target_guid.clone(),
@@ -43,12 +51,14 @@ impl ToAccountQuery for TargetAccountParams {
             parent_filter: self.target_parent_guid.clone(),
             type_filter: self.target_account_type.clone(),
             parent_name_filter: self.target_parent_name.clone(),
+            path_filter: self.target_path.clone(),
+            matcher,
         }
     }
 }
 
 impl ToAccountQuery for FromAccountParams {
-    fn build(&self, limit: Option<i64>) -> AccountQuery {
+    fn build(&self, limit: Option<i64>, matcher: TextMatcher) -> AccountQuery {
         AccountQuery {
             limit: limit.unwrap_or(10),
             guid_filter: self.from_guid.clone(),
@@ -56,12 +66,14 @@ impl ToAccountQuery for FromAccountParams {
             parent_filter: self.from_parent_guid.clone(),
             type_filter: self.from_account_type.clone(),
             parent_name_filter: self.from_parent_name.clone(),
+            path_filter: self.from_path.clone(),
+            matcher,
         }
     }
 }
 
 impl ToAccountQuery for FeeAccountParams {
-    fn build(&self, limit: Option<i64>) -> AccountQuery {
+    fn build(&self, limit: Option<i64>, matcher: TextMatcher) -> AccountQuery {
         AccountQuery {
             limit: limit.unwrap_or(10),
             guid_filter: self.fee_guid.clone(),
@@ -69,55 +81,77 @@ impl ToAccountQuery for FeeAccountParams {
             parent_filter: self.fee_parent_guid.clone(),
             type_filter: self.fee_account_type.clone(),
             parent_name_filter: self.fee_parent_name.clone(),
+            path_filter: self.fee_path.clone(),
+            matcher,
         }
     }
 }
 
 impl AccountQuery {
-    pub fn execute(&self, connection: &mut SqliteConnection) -> Vec<Account> {
+    pub fn execute(&self, connection: &mut SqliteConnection) -> Result<Vec<Account>> {
         use crate::schema::accounts;
 
         let mut query = accounts::table.into_boxed();
+        if let Some(ref path_txt) = self.path_filter {
+            let tree = AccountTree::load(connection)?;
+            let found = tree
+                .find_by_path(path_txt)
+                .map(|account| account.guid.clone());
+            match found {
+                Some(found_guid) => query = query.filter(accounts::guid.eq(found_guid)),
+                None => return Ok(Vec::new()),
+            }
+        }
         if let Some(ref guid_txt) = self.guid_filter {
-            query = query.filter(accounts::guid.like(format!("%{}%", guid_txt)));
+            query = filter_text!(query, accounts::guid, self.matcher, guid_txt);
         }
         if let Some(ref name_txt) = self.name_filter {
-            query = query.filter(accounts::name.like(format!("%{}%", name_txt)));
+            query = filter_text!(query, accounts::name, self.matcher, name_txt);
         }
         if let Some(ref parent_txt) = self.parent_filter {
-            query = query.filter(accounts::parent_guid.like(format!("%{}%", parent_txt)));
+            query = filter_text!(query, accounts::parent_guid, self.matcher, parent_txt);
         }
         if let Some(ref type_txt) = self.type_filter {
-            query = query.filter(accounts::account_type.like(format!("%{}%", type_txt)));
+            query = filter_text!(query, accounts::account_type, self.matcher, type_txt);
         }
         if let Some(ref parent_name_txt) = self.parent_name_filter {
-            let subquery = accounts::table
-                .filter(accounts::name.like(format!("%{}%", parent_name_txt)))
+            let parents = accounts::table
                 .select(accounts::guid.nullable())
                 .into_boxed();
+            let subquery = filter_text!(parents, accounts::name, self.matcher, parent_name_txt);
             query = query.filter(accounts::parent_guid.eq_any(subquery));
         }
 
-        query
+        Ok(query
             .limit(self.limit)
             .load::<Account>(connection)
-            .expect("Error loading accounts")
+            .expect("Error loading accounts"))
+    }
+
+    pub fn has_filter(&self) -> bool {
+        self.guid_filter.is_some()
+            || self.name_filter.is_some()
+            || self.parent_filter.is_some()
+            || self.type_filter.is_some()
+            || self.parent_name_filter.is_some()
+            || self.path_filter.is_some()
     }
 
-    pub fn execute_and_display(&self, connection: &mut SqliteConnection) {
-        let results = self.execute(connection);
+    pub fn execute_and_display(&self, connection: &mut SqliteConnection) -> Result<()> {
+        let results = self.execute(connection)?;
         println!("Displaying {} accounts", results.len());
         for account in results {
             account.display();
         }
+        Ok(())
     }
 
     pub fn get_one(
         &self,
         connection: &mut SqliteConnection,
         show_warning: bool,
-    ) -> Option<Account> {
-        let mut account_list = self.execute(connection);
+    ) -> Result<Option<Account>> {
+        let mut account_list = self.execute(connection)?;
         if account_list.len() != 1 {
             if show_warning {
                 println!(
@@ -128,9 +162,9 @@ impl AccountQuery {
                     acc.display();
                 }
             }
-            return None;
+            return Ok(None);
         }
-        account_list.pop()
+        Ok(account_list.pop())
     }
 }
 
@@ -141,7 +175,7 @@ impl fmt::Display for AccountQuery {
         // stream: `f`. Returns `fmt::Result` which indicates whether the
         // operation succeeded or failed. Note that `write!` uses syntax which
         // is very similar to `println!`.
-        write!(f, "limit:{}", self.limit)?;
+        write!(f, "limit:{} match:{:?}", self.limit, self.matcher.mode)?;
         if let Some(ref name_filter) = self.name_filter {
             write!(f, " name-filter:{}", name_filter)?;
         }
@@ -154,6 +188,9 @@ impl fmt::Display for AccountQuery {
         if let Some(ref type_filter) = self.type_filter {
             write!(f, " type-filter:{}", type_filter)?;
         }
+        if let Some(ref path_filter) = self.path_filter {
+            write!(f, " path:{}", path_filter)?;
+        }
         Ok(())
     }
 }
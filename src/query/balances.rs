@@ -0,0 +1,318 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::AddAssign;
+
+use chrono::NaiveDate;
+use diesel::prelude::*;
+
+use crate::models::Account;
+use crate::query::account_tree::AccountTree;
+use crate::query::transactions::TransactionQuery;
+use crate::utils::format_sqlite_date;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Balance {
+    pub total: f64,
+    // Cleared or reconciled splits
+    pub cleared: f64,
+    pub reconciled: f64,
+}
+
+impl Balance {
+    pub fn is_zero(&self) -> bool {
+        self.total == 0.0 && self.cleared == 0.0 && self.reconciled == 0.0
+    }
+
+    // Frozen splits are in a closed reconciliation, like GnuCash, they are reconciled too
+    pub fn add_split(&mut self, reconcile_state: &str, quantity: f64) {
+        self.total += quantity;
+        match reconcile_state {
+            "y" | "f" => {
+                self.cleared += quantity;
+                self.reconciled += quantity;
+            }
+            "c" => self.cleared += quantity,
+            _ => {}
+        }
+    }
+
+    // Without turning the zeros into -0.00
+    pub fn negate(self) -> Self {
+        Balance {
+            total: 0.0 - self.total,
+            cleared: 0.0 - self.cleared,
+            reconciled: 0.0 - self.reconciled,
+        }
+    }
+}
+
+impl AddAssign for Balance {
+    fn add_assign(&mut self, other: Balance) {
+        self.total += other.total;
+        self.cleared += other.cleared;
+        self.reconciled += other.reconciled;
+    }
+}
+
+// The balance of the account after each of its splits, by split guid. The splits are
+// summed from the first one, in post date order, as the transaction listing sorts them.
+pub fn running_balances(
+    connection: &mut SqliteConnection,
+    account_id: &str,
+) -> HashMap<String, Balance> {
+    let mut splits = TransactionQuery::load_account_splits(connection, account_id, None, None);
+    splits.sort_by(|(a, a_tx), (b, b_tx)| {
+        a_tx.post_date
+            .cmp(&b_tx.post_date)
+            .then_with(|| a.guid.cmp(&b.guid))
+    });
+    let mut balance = Balance::default();
+    splits
+        .into_iter()
+        .map(|(split, _)| {
+            balance.add_split(&split.reconcile_state, split.quantity());
+            (split.guid, balance)
+        })
+        .collect()
+}
+
+// The sum of the split quantities of every account, in the commodity of the account
+pub struct AccountBalances {
+    balances: HashMap<String, Balance>,
+}
+
+impl AccountBalances {
+    // Sums the splits posted between the dates, both ends are inclusive and optional
+    pub fn load(
+        connection: &mut SqliteConnection,
+        after: Option<NaiveDate>,
+        before: Option<NaiveDate>,
+    ) -> Self {
+        use crate::schema::{splits, transactions};
+
+        let mut query = splits::table
+            .inner_join(transactions::table)
+            .select((
+                splits::account_guid,
+                splits::reconcile_state,
+                splits::quantity_num,
+                splits::quantity_denom,
+            ))
+            .into_boxed();
+        if let Some(after_date) = after {
+            let after_as_txt =
+                format_sqlite_date(&after_date.and_hms_opt(0, 0, 0).expect("Correct date"));
+            query = query.filter(transactions::post_date.ge(after_as_txt));
+        }
+        if let Some(before_date) = before {
+            let before_as_txt =
+                format_sqlite_date(&before_date.and_hms_opt(23, 59, 59).expect("Correct date"));
+            query = query.filter(transactions::post_date.le(before_as_txt));
+        }
+        let rows = query
+            .load::<(String, String, i64, i64)>(connection)
+            .expect("Error loading splits");
+
+        let mut balances: HashMap<String, Balance> = HashMap::new();
+        for (account_guid, reconcile_state, quantity_num, quantity_denom) in rows {
+            if quantity_denom == 0 {
+                continue;
+            }
+            let quantity = quantity_num as f64 / quantity_denom as f64;
+            balances
+                .entry(account_guid)
+                .or_default()
+                .add_split(&reconcile_state, quantity);
+        }
+        AccountBalances { balances }
+    }
+
+    pub fn own(&self, guid: &str) -> Balance {
+        self.balances.get(guid).copied().unwrap_or_default()
+    }
+
+    // The balance of the account, and optionally of every account under it, by commodity
+    pub fn by_commodity(
+        &self,
+        tree: &AccountTree,
+        account: &Account,
+        rollup: bool,
+    ) -> BTreeMap<String, Balance> {
+        let accounts = if rollup {
+            tree.subtree(&account.guid)
+        } else {
+            vec![account]
+        };
+        let mut result: BTreeMap<String, Balance> = BTreeMap::new();
+        for current in accounts {
+            *result
+                .entry(tree.commodity(current).to_owned())
+                .or_default() += self.own(&current.guid);
+        }
+        result
+    }
+}
+
+pub struct BalanceDisplay {
+    pub rollup: bool,
+    // Keep the stored sign of the credit accounts
+    pub raw_sign: bool,
+}
+
+impl BalanceDisplay {
+    // The balance as shown, the credit accounts are positive when they have a credit balance
+    fn signed(&self, account: &Account, balance: Balance) -> Balance {
+        if account.is_credit_account() && !self.raw_sign {
+            balance.negate()
+        } else {
+            balance
+        }
+    }
+
+    pub fn display(
+        &self,
+        tree: &AccountTree,
+        balances: &AccountBalances,
+        roots: &[&Account],
+    ) -> usize {
+        println!(
+            "{:<60} {:>15} {:>15} {:>15}",
+            "Account", "Total", "Cleared", "Reconciled"
+        );
+        let accounts = tree.walk(roots, None);
+        for (account, depth) in &accounts {
+            let indent = "  ".repeat(depth.saturating_sub(1));
+            let name = format!(
+                "{}{}",
+                indent,
+                tree.full_name(&account.guid).unwrap_or_default()
+            );
+            let per_commodity = balances.by_commodity(tree, account, self.rollup);
+            if per_commodity.values().all(|balance| balance.is_zero()) {
+                println!(
+                    "{:<60} {:>15} {:>15} {:>15} {}",
+                    name,
+                    "0.00",
+                    "0.00",
+                    "0.00",
+                    tree.commodity(account)
+                );
+                continue;
+            }
+            let mut first = true;
+            for (commodity, balance) in per_commodity.iter().filter(|(_, b)| !b.is_zero()) {
+                let balance = self.signed(account, *balance);
+                println!(
+                    "{:<60} {:>15.2} {:>15.2} {:>15.2} {}",
+                    if first { name.as_str() } else { "" },
+                    balance.total,
+                    balance.cleared,
+                    balance.reconciled,
+                    commodity
+                );
+                first = false;
+            }
+        }
+        accounts.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account(guid: &str, parent: &str, account_type: &str, commodity: &str) -> (String, Account) {
+        let account = Account {
+            guid: guid.to_owned(),
+            name: guid.to_owned(),
+            account_type: account_type.to_owned(),
+            commodity_guid: Some(commodity.to_owned()),
+            commodity_scu: 100,
+            non_std_scu: 0,
+            parent_guid: Some(parent.to_owned()),
+            code: None,
+            description: None,
+            hidden: None,
+            placeholder: None,
+        };
+        (guid.to_owned(), account)
+    }
+
+    fn balance(total: f64, cleared: f64, reconciled: f64) -> Balance {
+        Balance {
+            total,
+            cleared,
+            reconciled,
+        }
+    }
+
+    #[test]
+    fn test_add_split() {
+        let mut sum = Balance::default();
+        sum.add_split("y", 100.0);
+        sum.add_split("c", 20.0);
+        sum.add_split("n", 5.0);
+        sum.add_split("v", 1.0);
+        sum.add_split("f", 30.0);
+        assert_eq!(sum, balance(156.0, 150.0, 130.0));
+    }
+
+    #[test]
+    fn test_by_commodity_rollup() {
+        let tree = AccountTree::new(
+            "root".to_owned(),
+            vec![
+                account("assets", "root", "ASSET", "huf"),
+                account("cash", "assets", "CASH", "huf"),
+                account("euro", "assets", "BANK", "eur"),
+            ]
+            .into_iter()
+            .collect(),
+            vec![
+                ("huf".to_owned(), ("HUF".to_owned(), 1)),
+                ("eur".to_owned(), ("EUR".to_owned(), 100)),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        let balances = AccountBalances {
+            balances: vec![
+                ("assets".to_owned(), balance(10.0, 10.0, 0.0)),
+                ("cash".to_owned(), balance(5.0, 0.0, 0.0)),
+                ("euro".to_owned(), balance(3.0, 3.0, 3.0)),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        let assets = tree.get("assets").unwrap();
+
+        let own = balances.by_commodity(&tree, assets, false);
+        assert_eq!(own.len(), 1);
+        assert_eq!(own["HUF"], balance(10.0, 10.0, 0.0));
+
+        let rolled_up = balances.by_commodity(&tree, assets, true);
+        assert_eq!(rolled_up.len(), 2);
+        assert_eq!(rolled_up["HUF"], balance(15.0, 10.0, 0.0));
+        assert_eq!(rolled_up["EUR"], balance(3.0, 3.0, 3.0));
+    }
+
+    #[test]
+    fn test_credit_accounts_are_negated() {
+        let (_, income) = account("salary", "root", "INCOME", "huf");
+        let (_, expense) = account("food", "root", "EXPENSE", "huf");
+        let stored = balance(-100.0, -100.0, 0.0);
+        let display = BalanceDisplay {
+            rollup: false,
+            raw_sign: false,
+        };
+        let shown = display.signed(&income, stored);
+        assert_eq!(shown, balance(100.0, 100.0, 0.0));
+        assert!(shown.reconciled.is_sign_positive());
+        assert_eq!(display.signed(&expense, stored), stored);
+
+        let raw = BalanceDisplay {
+            rollup: false,
+            raw_sign: true,
+        };
+        assert_eq!(raw.signed(&income, stored), stored);
+    }
+}
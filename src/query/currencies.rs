@@ -1,30 +1,36 @@
 use anyhow::Result;
 use diesel::prelude::*;
 
+use crate::query::text_match::{filter_text, TextMatcher};
 use crate::{cli::CommoditiesArgs, models::Commodities};
 
 pub struct CommoditiesQuery {
     pub limit: i64,
     pub name_filter: Option<String>,
     pub type_filter: Option<String>,
+    pub matcher: TextMatcher,
 }
 
 impl CommoditiesQuery {
-    pub fn execute(&self, connection: &mut SqliteConnection) -> Vec<Commodities> {
+    pub fn with_matcher(self, matcher: TextMatcher) -> Self {
+        CommoditiesQuery { matcher, ..self }
+    }
+
+    pub fn execute(&self, connection: &mut SqliteConnection) -> Result<Vec<Commodities>> {
         use crate::schema::commodities::dsl::*;
 
         let mut query = commodities.into_boxed();
         if let Some(ref name_txt) = self.name_filter {
-            query = query.filter(mnemonic.like(format!("%{}%", name_txt)));
+            query = filter_text!(query, mnemonic, self.matcher, name_txt);
         }
         if let Some(ref type_txt) = self.type_filter {
-            query = query.filter(namespace.like(format!("%{}%", type_txt)));
+            query = filter_text!(query, namespace, self.matcher, type_txt);
         }
 
-        query
+        Ok(query
             .limit(self.limit)
             .load::<Commodities>(connection)
-            .expect("Error loading commodities")
+            .expect("Error loading commodities"))
     }
     /*
         pub fn to_map(&self, connection: &SqliteConnection) -> BTreeMap<String, Commodities> {
@@ -37,7 +43,7 @@ impl CommoditiesQuery {
         }
     */
     pub fn execute_and_display(&self, connection: &mut SqliteConnection) -> Result<usize> {
-        let results = self.execute(connection);
+        let results = self.execute(connection)?;
         println!("Displaying {} commodities", results.len());
         let len = results.len();
         for commodity in results {
@@ -56,6 +62,18 @@ impl CommoditiesQuery {
             .expect("Error loading a commodity")
             .pop()
     }
+
+    pub fn get_currency(connection: &mut SqliteConnection, code: &str) -> Option<Commodities> {
+        use crate::schema::commodities::dsl::*;
+
+        commodities
+            .filter(mnemonic.eq(code))
+            .filter(namespace.eq_any(["CURRENCY", "ISO4217"]))
+            .limit(1)
+            .load::<Commodities>(connection)
+            .expect("Error loading a currency")
+            .pop()
+    }
 }
 
 impl From<CommoditiesArgs> for CommoditiesQuery {
@@ -64,6 +82,7 @@ impl From<CommoditiesArgs> for CommoditiesQuery {
             limit: args.limit.unwrap_or(10),
             name_filter: args.name,
             type_filter: args.commodity_type,
+            matcher: TextMatcher::default(),
         }
     }
 }
@@ -0,0 +1,38 @@
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::{BigInt, Text};
+
+// The indexes which GnuCash creates, and the queries of the correlation rely on.
+const EXPECTED_INDEXES: &[(&str, &str)] = &[
+    ("splits", "account_guid"),
+    ("splits", "tx_guid"),
+    ("transactions", "post_date"),
+    ("slots", "obj_guid"),
+];
+
+#[derive(QueryableByName)]
+struct IndexCount {
+    #[diesel(sql_type = BigInt)]
+    count: i64,
+}
+
+fn has_index(connection: &mut SqliteConnection, table: &str, column: &str) -> bool {
+    sql_query(
+        "SELECT count(*) AS count FROM pragma_index_list(?) AS il, pragma_index_info(il.name) AS ii \
+         WHERE ii.seqno = 0 AND ii.name = ?",
+    )
+    .bind::<Text, _>(table)
+    .bind::<Text, _>(column)
+    .get_result::<IndexCount>(connection)
+    .map(|result| result.count > 0)
+    .unwrap_or(true)
+}
+
+// Returns the expected, but missing indexes, like 'splits(account_guid)'
+pub fn missing_indexes(connection: &mut SqliteConnection) -> Vec<String> {
+    EXPECTED_INDEXES
+        .iter()
+        .filter(|(table, column)| !has_index(connection, table, column))
+        .map(|(table, column)| format!("{}({})", table, column))
+        .collect()
+}
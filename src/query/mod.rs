@@ -1,3 +1,10 @@
+pub mod account_tree;
 pub mod accounts;
+pub mod balances;
 pub mod currencies;
+pub mod indexes;
+pub mod prices;
+#[cfg(test)]
+pub mod test_book;
+pub mod text_match;
 pub mod transactions;
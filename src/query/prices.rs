@@ -0,0 +1,148 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use chrono::NaiveDate;
+use diesel::prelude::*;
+
+use crate::utils::format_sqlite_date;
+
+// The latest known exchange rate between each pair of commodities at a given date,
+// usable in both directions, and through other commodities.
+pub struct PriceTable {
+    // commodity guid -> (other commodity guid, price of one unit in the other)
+    rates: HashMap<String, Vec<(String, f64)>>,
+}
+
+impl PriceTable {
+    pub fn load(connection: &mut SqliteConnection, as_of: NaiveDate) -> Self {
+        use crate::schema::prices;
+
+        let as_of_txt = format_sqlite_date(&as_of.and_hms_opt(23, 59, 59).expect("Correct date"));
+        let rows = prices::table
+            .filter(prices::date.le(as_of_txt))
+            .select((
+                prices::commodity_guid,
+                prices::currency_guid,
+                prices::date,
+                prices::value_num,
+                prices::value_denom,
+            ))
+            .load::<(String, String, String, i64, i64)>(connection)
+            .expect("Error loading prices");
+        let prices = rows
+            .into_iter()
+            .filter(|(_, _, _, value_num, value_denom)| *value_num != 0 && *value_denom != 0)
+            .map(|(commodity, currency, date, value_num, value_denom)| {
+                (
+                    commodity,
+                    currency,
+                    date,
+                    value_num as f64 / value_denom as f64,
+                )
+            });
+        PriceTable::new(latest_by_pair(prices))
+    }
+
+    fn new(latest: HashMap<(String, String), f64>) -> Self {
+        let mut rates: HashMap<String, Vec<(String, f64)>> = HashMap::new();
+        for ((commodity, currency), price) in latest {
+            rates
+                .entry(commodity.clone())
+                .or_default()
+                .push((currency.clone(), price));
+            rates
+                .entry(currency)
+                .or_default()
+                .push((commodity, 1.0 / price));
+        }
+        PriceTable { rates }
+    }
+
+    // The value of one unit of the commodity in the target commodity, using the
+    // fewest conversions, if there is any path between them.
+    pub fn rate(&self, from: &str, to: &str) -> Option<f64> {
+        if from == to {
+            return Some(1.0);
+        }
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::new();
+        visited.insert(from);
+        queue.push_back((from, 1.0));
+        while let Some((current, rate)) = queue.pop_front() {
+            for (next, price) in self.rates.get(current).into_iter().flatten() {
+                if next == to {
+                    return Some(rate * price);
+                }
+                if visited.insert(next.as_str()) {
+                    queue.push_back((next.as_str(), rate * price));
+                }
+            }
+        }
+        None
+    }
+}
+
+// The latest price of every pair of commodities, whichever direction it was quoted in,
+// the prices are (commodity, currency, date, price of the commodity in the currency).
+fn latest_by_pair<I>(prices: I) -> HashMap<(String, String), f64>
+where
+    I: IntoIterator<Item = (String, String, String, f64)>,
+{
+    let mut latest: HashMap<(String, String), (String, f64)> = HashMap::new();
+    for (commodity, currency, date, price) in prices {
+        // the key is ordered, so both directions of a pair get the same one
+        let (key, price) = if commodity <= currency {
+            ((commodity, currency), price)
+        } else {
+            ((currency, commodity), 1.0 / price)
+        };
+        match latest.get(&key) {
+            Some((latest_date, _)) if *latest_date > date => {}
+            _ => {
+                latest.insert(key, (date, price));
+            }
+        }
+    }
+    latest
+        .into_iter()
+        .map(|(key, (_, price))| (key, price))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cross_rates() {
+        let mut latest = HashMap::new();
+        latest.insert(("EUR".to_owned(), "HUF".to_owned()), 400.0);
+        latest.insert(("EUR".to_owned(), "USD".to_owned()), 1.25);
+        latest.insert(("AAPL".to_owned(), "USD".to_owned()), 150.0);
+        let prices = PriceTable::new(latest);
+        assert_eq!(prices.rate("HUF", "HUF"), Some(1.0));
+        assert_eq!(prices.rate("EUR", "HUF"), Some(400.0));
+        assert_eq!(prices.rate("HUF", "EUR"), Some(1.0 / 400.0));
+        assert_eq!(prices.rate("USD", "HUF"), Some(320.0));
+        assert_eq!(prices.rate("AAPL", "HUF"), Some(48000.0));
+        assert_eq!(prices.rate("GBP", "HUF"), None);
+    }
+
+    #[test]
+    fn test_newer_price_in_reverse_direction() {
+        let price = |commodity: &str, currency: &str, date: &str, price| {
+            (
+                commodity.to_owned(),
+                currency.to_owned(),
+                date.to_owned(),
+                price,
+            )
+        };
+        let prices = PriceTable::new(latest_by_pair(vec![
+            price("EUR", "HUF", "2023-01-10 10:00:00", 400.0),
+            price("HUF", "EUR", "2023-03-01 10:00:00", 0.0025),
+            price("EUR", "HUF", "2023-02-01 10:00:00", 390.0),
+        ]));
+        assert_eq!(prices.rate("HUF", "EUR"), Some(0.0025));
+        assert_eq!(prices.rate("EUR", "HUF"), Some(1.0 / 0.0025));
+    }
+}
@@ -0,0 +1,65 @@
+use diesel::prelude::*;
+
+// An in-memory database with the tables of the splits, transactions and slots, for the query tests
+pub fn empty_book() -> SqliteConnection {
+    let mut connection = SqliteConnection::establish(":memory:").unwrap();
+    diesel::sql_query(
+        "CREATE TABLE transactions (guid TEXT PRIMARY KEY, currency_guid TEXT NOT NULL,
+            num TEXT NOT NULL, post_date TEXT, enter_date TEXT, description TEXT)",
+    )
+    .execute(&mut connection)
+    .unwrap();
+    diesel::sql_query(
+        "CREATE TABLE splits (guid TEXT PRIMARY KEY, tx_guid TEXT NOT NULL,
+            account_guid TEXT NOT NULL, memo TEXT NOT NULL, action TEXT NOT NULL,
+            reconcile_state TEXT NOT NULL, reconcile_date TEXT,
+            value_num BIGINT NOT NULL, value_denom BIGINT NOT NULL,
+            quantity_num BIGINT NOT NULL, quantity_denom BIGINT NOT NULL, lot_guid TEXT)",
+    )
+    .execute(&mut connection)
+    .unwrap();
+    diesel::sql_query(
+        "CREATE TABLE slots (id INTEGER PRIMARY KEY AUTOINCREMENT, obj_guid TEXT NOT NULL,
+            name TEXT NOT NULL, slot_type INTEGER NOT NULL, int64_val BIGINT, string_val TEXT,
+            double_val REAL, timespec_val TEXT, guid_val TEXT, numeric_val_num BIGINT,
+            numeric_val_denom BIGINT, gdate_val TEXT)",
+    )
+    .execute(&mut connection)
+    .unwrap();
+    connection
+}
+
+// A transaction with a single split, the quantity and the value are in hundredths
+pub fn add_split(
+    connection: &mut SqliteConnection,
+    id: usize,
+    account: &str,
+    date: &str,
+    quantity: i64,
+    value: i64,
+) {
+    diesel::sql_query(format!(
+        "INSERT INTO transactions VALUES ('tx{}', 'currency', '', '{} 10:00:00', NULL, NULL)",
+        id, date
+    ))
+    .execute(connection)
+    .unwrap();
+    diesel::sql_query(format!(
+        "INSERT INTO splits VALUES ('split{0}', 'tx{0}', '{1}', '', '', 'n', NULL,
+            {2}, 100, {3}, 100, NULL)",
+        id, account, value, quantity
+    ))
+    .execute(connection)
+    .unwrap();
+}
+
+// Stores the bank's transaction id of the split, added by add_split with the same id
+pub fn add_online_id(connection: &mut SqliteConnection, id: usize, online_id: &str) {
+    diesel::sql_query(format!(
+        "INSERT INTO slots (obj_guid, name, slot_type, string_val)
+            VALUES ('split{}', 'online_id', 4, '{}')",
+        id, online_id
+    ))
+    .execute(connection)
+    .unwrap();
+}
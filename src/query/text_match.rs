@@ -0,0 +1,151 @@
+use std::sync::Mutex;
+
+use anyhow::Result;
+use clap::ValueEnum;
+use diesel::prelude::*;
+use diesel::sql_types::{Nullable, Text};
+use regex::Regex;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum MatchMode {
+    // The whole value equals to the filter
+    Exact,
+    // The value starts with the filter
+    Prefix,
+    // The value contains the filter anywhere
+    #[default]
+    Contains,
+    // The filter is a regular expression, searched in the value
+    Regex,
+}
+
+// How the text filters of the account, transaction and commodity queries are compared
+// with the values. Without case sensitivity, only ASCII letters are folded by SQLite.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TextMatcher {
+    pub mode: MatchMode,
+    pub case_sensitive: bool,
+}
+
+// The SQL condition to use for one filter
+#[derive(Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Equal(String),
+    // LIKE pattern, with '\' as the escape character
+    Like(String),
+    Regex(String),
+}
+
+sql_function!(fn regexp(pattern: Text, value: Nullable<Text>) -> diesel::sql_types::Bool);
+
+// Makes the regexp() function, and so the 'x REGEXP y' operator available in the queries.
+pub fn register_regexp(connection: &mut SqliteConnection) -> QueryResult<()> {
+    // the pattern is the same for every row, so the last compiled one is kept
+    let last: Mutex<Option<Regex>> = Mutex::new(None);
+    regexp::register_impl(connection, move |pattern: String, value: Option<String>| {
+        let mut last = last.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if last.as_ref().map(|regex| regex.as_str()) != Some(pattern.as_str()) {
+            *last = Regex::new(&pattern).ok();
+        }
+        match (last.as_ref(), value) {
+            (Some(regex), Some(text)) => regex.is_match(&text),
+            _ => false,
+        }
+    })
+}
+
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+impl TextMatcher {
+    pub fn comparison(&self, text: &str) -> Result<Comparison> {
+        let comparison = match (self.mode, self.case_sensitive) {
+            (MatchMode::Exact, true) => Comparison::Equal(text.to_owned()),
+            (MatchMode::Exact, false) => Comparison::Like(escape_like(text)),
+            (MatchMode::Prefix, true) => Comparison::Regex(format!("^{}", regex::escape(text))),
+            (MatchMode::Prefix, false) => Comparison::Like(format!("{}%", escape_like(text))),
+            (MatchMode::Contains, true) => Comparison::Regex(regex::escape(text)),
+            (MatchMode::Contains, false) => Comparison::Like(format!("%{}%", escape_like(text))),
+            (MatchMode::Regex, case_sensitive) => {
+                let pattern = if case_sensitive {
+                    text.to_owned()
+                } else {
+                    format!("(?i){}", text)
+                };
+                Regex::new(&pattern)
+                    .map_err(|error| anyhow!("Invalid regular expression '{}': {}", text, error))?;
+                Comparison::Regex(pattern)
+            }
+        };
+        Ok(comparison)
+    }
+}
+
+// Adds a filter on the text column to the boxed query, as the matcher compares them.
+// Returns early with the error of an invalid pattern.
+macro_rules! filter_text {
+    ($query:expr, $column:expr, $matcher:expr, $text:expr) => {
+        match $matcher.comparison($text)? {
+            $crate::query::text_match::Comparison::Equal(value) => $query.filter($column.eq(value)),
+            $crate::query::text_match::Comparison::Like(pattern) => {
+                $query.filter($column.like(pattern).escape('\\'))
+            }
+            $crate::query::text_match::Comparison::Regex(pattern) => $query.filter(
+                $crate::query::text_match::regexp(pattern, $column.nullable()),
+            ),
+        }
+    };
+}
+
+pub(crate) use filter_text;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_comparison() {
+        let matcher = |mode, case_sensitive| TextMatcher {
+            mode,
+            case_sensitive,
+        };
+        assert_eq!(
+            matcher(MatchMode::Contains, false)
+                .comparison("50%_off")
+                .unwrap(),
+            Comparison::Like("%50\\%\\_off%".to_owned())
+        );
+        assert_eq!(
+            matcher(MatchMode::Prefix, true)
+                .comparison("Food.x")
+                .unwrap(),
+            Comparison::Regex("^Food\\.x".to_owned())
+        );
+        assert_eq!(
+            matcher(MatchMode::Exact, true).comparison("Food").unwrap(),
+            Comparison::Equal("Food".to_owned())
+        );
+        assert_eq!(
+            matcher(MatchMode::Regex, false).comparison("^F").unwrap(),
+            Comparison::Regex("(?i)^F".to_owned())
+        );
+        assert!(matcher(MatchMode::Regex, true).comparison("Food(").is_err());
+    }
+
+    #[test]
+    fn test_regexp_function() {
+        let connection = &mut SqliteConnection::establish(":memory:").unwrap();
+        register_regexp(connection).unwrap();
+        let mut matches = |pattern: &str, value: Option<&str>| {
+            diesel::select(regexp(pattern, value))
+                .get_result::<bool>(connection)
+                .unwrap()
+        };
+        assert!(matches("^Food$", Some("Food")));
+        assert!(!matches("^Food$", Some("Seafood")));
+        assert!(!matches("Food", None));
+    }
+}
@@ -1,36 +1,127 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
 use anyhow::Result;
 use chrono::naive::NaiveDate;
+use clap::ValueEnum;
 use console::{style, Term};
+use diesel::dsl::sql;
+use diesel::helper_types::InnerJoinQuerySource;
 use diesel::prelude::*;
+use diesel::sql_types::{Bool, Double};
+use diesel::sqlite::Sqlite;
 
 use crate::cli::TransactionsArgs;
+use crate::dbmodifier::ONLINE_ID_SLOT;
 use crate::models::{Account, Split, Transaction};
-use crate::utils::{format_sqlite_date, to_date};
+use crate::query::account_tree::AccountTree;
+use crate::query::balances::running_balances;
+use crate::query::text_match::{filter_text, TextMatcher};
+use crate::schema::{splits, transactions};
+use crate::utils::{format_sqlite_date, parse_date_arg, to_date, DenominatedValue};
+
+type SplitCondition = Box<
+    dyn BoxableExpression<
+        InnerJoinQuerySource<splits::table, transactions::table>,
+        Sqlite,
+        SqlType = Bool,
+    >,
+>;
+
+// The amount filters of the query on one num/denom column pair of the splits, the amounts
+// are compared as num1 * denom2 <= num2 * denom1, to be exact
+macro_rules! amount_condition {
+    ($query:expr, $num:expr, $denom:expr) => {{
+        let mut condition: SplitCondition = Box::new(true.into_sql::<Bool>());
+        if let Some(ref min) = $query.min_amount {
+            condition = Box::new(condition.and(($num * min.denom).ge($denom * min.value)));
+        }
+        if let Some(ref max) = $query.max_amount {
+            condition = Box::new(condition.and(($num * max.denom).le($denom * max.value)));
+        }
+        if let Some(ref exact) = $query.amount {
+            condition = Box::new(condition.and(($num * exact.denom).eq($denom * exact.value)));
+        }
+        condition
+    }};
+}
+
+const AMOUNT_SQL: &str = "CAST(splits.quantity_num AS REAL) / splits.quantity_denom";
 
 pub struct TransactionQuery {
     pub limit: i64,
     pub txid_filter: Option<String>,
-    pub account_filter: Option<String>,
+    // Exact guids of the accounts, any of them matches
+    pub account_ids: Option<Vec<String>>,
     pub description_filter: Option<String>,
     pub memo_filter: Option<String>,
     pub before_filter: Option<NaiveDate>,
     pub after_filter: Option<NaiveDate>,
+    // Splits with at least, at most, or exactly the given value or quantity
+    pub min_amount: Option<DenominatedValue>,
+    pub max_amount: Option<DenominatedValue>,
+    pub amount: Option<DenominatedValue>,
+    pub sort: SortKey,
+    pub descending: bool,
+    pub offset: i64,
+    pub matcher: TextMatcher,
+    // Splits in any of the reconcile states, and reconciled between the dates
+    pub reconcile_states: Vec<ReconcileState>,
+    pub reconciled_after: Option<NaiveDate>,
+    pub reconciled_before: Option<NaiveDate>,
+    // Show the balance of the single listed account after each split
+    pub running_balance: bool,
+    // Show the found transactions with all of their splits
+    pub group_by_transaction: bool,
+}
+
+// The reconcile_state of the splits, as GnuCash stores them
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ReconcileState {
+    #[value(name = "n", alias = "new")]
+    New,
+    #[value(name = "c", alias = "cleared")]
+    Cleared,
+    #[value(name = "y", alias = "reconciled")]
+    Reconciled,
+    #[value(name = "f", alias = "frozen")]
+    Frozen,
+    #[value(name = "v", alias = "void")]
+    Void,
+}
+
+impl ReconcileState {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ReconcileState::New => "n",
+            ReconcileState::Cleared => "c",
+            ReconcileState::Reconciled => "y",
+            ReconcileState::Frozen => "f",
+            ReconcileState::Void => "v",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SortKey {
+    Date,
+    Amount,
+    Description,
 }
 
 impl TransactionQuery {
-    pub fn with_account_id(self, account_id: String) -> Self {
+    pub fn with_account_ids(self, account_ids: Vec<String>) -> Self {
         TransactionQuery {
-            limit: self.limit,
-            txid_filter: self.txid_filter,
-            account_filter: Some(account_id),
-            description_filter: self.description_filter,
-            memo_filter: self.memo_filter,
-            before_filter: self.before_filter,
-            after_filter: self.after_filter,
+            account_ids: Some(account_ids),
+            ..self
         }
     }
 
-    pub fn execute(&self, connection: &mut SqliteConnection) -> Vec<(Split, Transaction)> {
+    pub fn with_matcher(self, matcher: TextMatcher) -> Self {
+        TransactionQuery { matcher, ..self }
+    }
+
+    pub fn execute(&self, connection: &mut SqliteConnection) -> Result<Vec<(Split, Transaction)>> {
         use crate::schema::splits::dsl::*;
         use crate::schema::transactions::dsl::*;
 
@@ -38,16 +129,16 @@ impl TransactionQuery {
 
         let mut query = join.into_boxed();
         if let Some(ref txid_txt) = self.txid_filter {
-            query = query.filter(tx_guid.like(format!("%{}%", txid_txt)));
+            query = filter_text!(query, tx_guid, self.matcher, txid_txt);
         }
-        if let Some(ref account_txt) = self.account_filter {
-            query = query.filter(account_guid.like(format!("%{}%", account_txt)));
+        if let Some(ref account_ids) = self.account_ids {
+            query = query.filter(account_guid.eq_any(account_ids));
         }
         if let Some(ref name_txt) = self.memo_filter {
-            query = query.filter(memo.like(format!("%{}%", name_txt)));
+            query = filter_text!(query, memo, self.matcher, name_txt);
         }
         if let Some(ref description_txt) = self.description_filter {
-            query = query.filter(description.like(format!("%{}%", description_txt)));
+            query = filter_text!(query, description, self.matcher, description_txt);
         }
         if let Some(after_date) = self.after_filter {
             let after_as_txt =
@@ -59,38 +150,193 @@ impl TransactionQuery {
                 format_sqlite_date(&before_date.and_hms_opt(23, 59, 59).expect("Correct date"));
             query = query.filter(post_date.le(before_as_txt));
         }
+        if !self.reconcile_states.is_empty() {
+            let codes: Vec<&str> = self
+                .reconcile_states
+                .iter()
+                .map(|state| state.code())
+                .collect();
+            query = query.filter(reconcile_state.eq_any(codes));
+        }
+        if let Some(after_date) = self.reconciled_after {
+            let after_as_txt =
+                format_sqlite_date(&after_date.and_hms_opt(0, 0, 0).expect("Correct date"));
+            query = query.filter(reconcile_date.ge(after_as_txt));
+        }
+        if let Some(before_date) = self.reconciled_before {
+            let before_as_txt =
+                format_sqlite_date(&before_date.and_hms_opt(23, 59, 59).expect("Correct date"));
+            query = query.filter(reconcile_date.le(before_as_txt));
+        }
+        // the quantity or the value should satisfy every amount filter on its own, as they
+        // can be in different commodities
+        if self.min_amount.is_some() || self.max_amount.is_some() || self.amount.is_some() {
+            let by_quantity = amount_condition!(self, quantity_num, quantity_denom);
+            let by_value = amount_condition!(self, value_num, value_denom);
+            query = query.filter(by_quantity.or(by_value));
+        }
+        query = match (self.sort, self.descending) {
+            (SortKey::Date, false) => query.order(post_date.asc()),
+            (SortKey::Date, true) => query.order(post_date.desc()),
+            (SortKey::Amount, false) => query.order(sql::<Double>(AMOUNT_SQL).asc()),
+            (SortKey::Amount, true) => query.order(sql::<Double>(AMOUNT_SQL).desc()),
+            (SortKey::Description, false) => query.order(description.asc()),
+            (SortKey::Description, true) => query.order(description.desc()),
+        };
 
-        query
+        Ok(query
+            .then_order_by(crate::schema::splits::guid)
             .limit(self.limit)
+            .offset(self.offset)
+            .load::<(Split, Transaction)>(connection)
+            .expect("Error loading splits"))
+    }
+
+    // Every split of the account posted between the dates, exact guid match to use the
+    // index of the splits, and without a limit, as the correlation needs all of them.
+    pub fn load_account_splits(
+        connection: &mut SqliteConnection,
+        account_id: &str,
+        after: Option<NaiveDate>,
+        before: Option<NaiveDate>,
+    ) -> Vec<(Split, Transaction)> {
+        let mut query = splits::table
+            .inner_join(transactions::table)
+            .filter(splits::account_guid.eq(account_id))
+            .into_boxed();
+        if let Some(after_date) = after {
+            let after_as_txt =
+                format_sqlite_date(&after_date.and_hms_opt(0, 0, 0).expect("Correct date"));
+            query = query.filter(transactions::post_date.ge(after_as_txt));
+        }
+        if let Some(before_date) = before {
+            let before_as_txt =
+                format_sqlite_date(&before_date.and_hms_opt(23, 59, 59).expect("Correct date"));
+            query = query.filter(transactions::post_date.le(before_as_txt));
+        }
+        query
             .load::<(Split, Transaction)>(connection)
             .expect("Error loading splits")
     }
 
+    // The bank transaction ids of the already imported splits in the account, mapped to the split guid
+    pub fn load_online_ids(
+        connection: &mut SqliteConnection,
+        account_id: &str,
+    ) -> HashMap<String, String> {
+        use crate::schema::{slots, splits};
+
+        slots::table
+            .inner_join(splits::table.on(slots::obj_guid.eq(splits::guid)))
+            .filter(splits::account_guid.eq(account_id))
+            .filter(slots::name.eq(ONLINE_ID_SLOT))
+            .select((slots::string_val, splits::guid))
+            .load::<(Option<String>, String)>(connection)
+            .expect("Error loading online ids")
+            .into_iter()
+            .filter_map(|(online_id, split_guid)| online_id.map(|id| (id, split_guid)))
+            .collect()
+    }
+
     pub fn execute_and_process(
         &self,
         connection: &mut SqliteConnection,
         target_account: &Option<Account>,
         term: &Term,
     ) -> Result<usize> {
-        let results = self.execute(connection);
+        let results = self.execute(connection)?;
         match target_account {
-            None => self.display(results),
+            None if self.group_by_transaction => self.display_grouped(connection, results),
+            None => self.display(connection, results),
             Some(account) => self.move_splits(connection, results, account, term),
         }
     }
 
-    fn display(&self, transactions: Vec<(Split, Transaction)>) -> Result<usize> {
+    fn display(
+        &self,
+        connection: &mut SqliteConnection,
+        transactions: Vec<(Split, Transaction)>,
+    ) -> Result<usize> {
+        let tree = AccountTree::load(connection)?;
+        let balances = match (self.running_balance, self.account_ids.as_deref()) {
+            (false, _) => None,
+            (true, Some([account_id])) => Some(running_balances(connection, account_id)),
+            (true, _) => return Err(anyhow!("The running balance needs exactly one account!")),
+        };
         let len = transactions.len();
         println!("Displaying {} splits", len);
         for (split, tx) in transactions {
-            println!(
-                "[{}]<{}> - {} - {}",
-                split.account_guid, split.tx_guid, tx, split
+            let account = tree.get(&split.account_guid);
+            let name = tree.full_name(&split.account_guid).unwrap_or_default();
+            let mut line = format!(
+                "[{}]<{}> {} - {} - {}",
+                split.account_guid,
+                split.tx_guid,
+                style(name).cyan(),
+                tx,
+                split
             );
+            if let (Some(balances), Some(account)) = (&balances, account) {
+                let balance = balances.get(&split.guid).copied().unwrap_or_default();
+                line.push_str(&format!(
+                    " | balance: {} cleared: {}",
+                    style(tree.format_amount(account, balance.total)).bold(),
+                    tree.format_amount(account, balance.cleared)
+                ));
+            }
+            println!("{}", line);
         }
         Ok(len)
     }
 
+    // Every split of the found transactions, with the full name of their accounts
+    fn display_grouped(
+        &self,
+        connection: &mut SqliteConnection,
+        found: Vec<(Split, Transaction)>,
+    ) -> Result<usize> {
+        use crate::schema::splits;
+
+        let tree = AccountTree::load(connection)?;
+        let mut transactions: Vec<Transaction> = Vec::new();
+        for (_, tx) in found {
+            if !transactions.iter().any(|existing| existing.guid == tx.guid) {
+                transactions.push(tx);
+            }
+        }
+        let tx_guids: Vec<&str> = transactions.iter().map(|tx| tx.guid.as_str()).collect();
+        let mut splits_by_tx: HashMap<String, Vec<Split>> = HashMap::new();
+        for split in splits::table
+            .filter(splits::tx_guid.eq_any(&tx_guids))
+            .load::<Split>(connection)
+            .expect("Error loading splits")
+        {
+            splits_by_tx
+                .entry(split.tx_guid.clone())
+                .or_default()
+                .push(split);
+        }
+        println!("Displaying {} transactions", transactions.len());
+        for tx in &transactions {
+            println!("{} <{}>", style(tx).cyan(), tx.guid);
+            for split in splits_by_tx.get(&tx.guid).into_iter().flatten() {
+                let (name, amount) = match tree.get(&split.account_guid) {
+                    Some(account) => (
+                        tree.full_name(&account.guid)
+                            .unwrap_or_else(|| account.name.clone()),
+                        tree.format_amount(account, split.quantity()),
+                    ),
+                    None => (split.account_guid.clone(), split.quantity().to_string()),
+                };
+                println!(
+                    "    {:<50} {:>20} [{}] {}",
+                    name, amount, split.reconcile_state, split.memo
+                );
+            }
+        }
+        Ok(transactions.len())
+    }
+
     fn move_splits(
         &self,
         connection: &mut SqliteConnection,
@@ -119,16 +365,88 @@ impl TransactionQuery {
     }
 }
 
-impl From<TransactionsArgs> for TransactionQuery {
-    fn from(args: TransactionsArgs) -> Self {
-        TransactionQuery {
+impl TryFrom<TransactionsArgs> for TransactionQuery {
+    type Error = anyhow::Error;
+
+    fn try_from(args: TransactionsArgs) -> Result<Self> {
+        let parse_amount = |amount: &Option<String>| {
+            amount
+                .as_deref()
+                .map(DenominatedValue::parse_decimal)
+                .transpose()
+        };
+        Ok(TransactionQuery {
             limit: args.limit.unwrap_or(10),
             txid_filter: args.txid,
-            account_filter: None,
+            account_ids: None,
             description_filter: args.description,
             memo_filter: args.memo,
             before_filter: to_date(args.before),
             after_filter: to_date(args.after),
+            min_amount: parse_amount(&args.min_amount)?,
+            max_amount: parse_amount(&args.max_amount)?,
+            amount: parse_amount(&args.amount)?,
+            sort: args.sort,
+            descending: args.descending,
+            offset: args.offset.unwrap_or(0),
+            matcher: TextMatcher::default(),
+            reconcile_states: args.reconcile_states,
+            reconciled_after: parse_date_arg("reconciled-after", &args.reconciled_after)?,
+            reconciled_before: parse_date_arg("reconciled-before", &args.reconciled_before)?,
+            running_balance: args.running_balance,
+            group_by_transaction: args.group_by_transaction,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::test_book;
+
+    fn query(min: &str, max: &str) -> TransactionQuery {
+        TransactionQuery {
+            limit: 10,
+            txid_filter: None,
+            account_ids: None,
+            description_filter: None,
+            memo_filter: None,
+            before_filter: None,
+            after_filter: None,
+            min_amount: DenominatedValue::parse_decimal(min).ok(),
+            max_amount: DenominatedValue::parse_decimal(max).ok(),
+            amount: None,
+            sort: SortKey::Date,
+            descending: false,
+            offset: 0,
+            matcher: TextMatcher::default(),
+            reconcile_states: Vec::new(),
+            reconciled_after: None,
+            reconciled_before: None,
+            running_balance: false,
+            group_by_transaction: false,
         }
     }
+
+    #[test]
+    fn test_amount_range_of_one_field() {
+        let connection = &mut test_book::empty_book();
+        // 1000 HUF paid with a card in EUR
+        test_book::add_split(connection, 1, "groceries", "2023-01-05", 100000, 300);
+        test_book::add_split(connection, 2, "groceries", "2023-01-06", 55000, 55000);
+        let found = |q: TransactionQuery, connection: &mut SqliteConnection| -> Vec<String> {
+            q.execute(connection)
+                .unwrap()
+                .into_iter()
+                .map(|(split, _)| split.guid)
+                .collect()
+        };
+        assert_eq!(found(query("500", "600"), connection), vec!["split2"]);
+        assert_eq!(found(query("2", "4"), connection), vec!["split1"]);
+        let exact = TransactionQuery {
+            amount: DenominatedValue::parse_decimal("3").ok(),
+            ..query("", "")
+        };
+        assert_eq!(found(exact, connection), vec!["split1"]);
+    }
 }
@@ -0,0 +1,172 @@
+use std::collections::BTreeSet;
+
+use chrono::NaiveDate;
+
+use crate::models::{Account, Commodities};
+use crate::query::account_tree::AccountTree;
+use crate::query::balances::AccountBalances;
+use crate::query::prices::PriceTable;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Section {
+    Assets,
+    Liabilities,
+    Equity,
+}
+
+fn section_of(account: &Account) -> Option<Section> {
+    match account.account_type.as_str() {
+        "ASSET" | "BANK" | "CASH" | "STOCK" | "MUTUAL" | "RECEIVABLE" | "TRADING" => {
+            Some(Section::Assets)
+        }
+        "LIABILITY" | "CREDIT" | "PAYABLE" => Some(Section::Liabilities),
+        "EQUITY" => Some(Section::Equity),
+        _ => None,
+    }
+}
+
+// The converted sum of some accounts, and the commodities which have no price
+#[derive(Default)]
+struct Converted {
+    amount: f64,
+    missing: BTreeSet<String>,
+}
+
+pub struct BalanceSheet<'a> {
+    pub date: NaiveDate,
+    pub currency: &'a Commodities,
+    pub max_depth: Option<usize>,
+}
+
+impl<'a> BalanceSheet<'a> {
+    fn convert(
+        &self,
+        tree: &AccountTree,
+        balances: &AccountBalances,
+        prices: &PriceTable,
+        accounts: &[&Account],
+    ) -> Converted {
+        let mut result = Converted::default();
+        for account in accounts {
+            let own = balances.own(&account.guid).total;
+            if own == 0.0 {
+                continue;
+            }
+            let rate = account
+                .commodity_guid
+                .as_ref()
+                .and_then(|guid| prices.rate(guid, &self.currency.guid));
+            match rate {
+                Some(rate) => result.amount += own * rate,
+                None => {
+                    result.missing.insert(tree.commodity(account).to_owned());
+                }
+            }
+        }
+        if accounts.first().is_some_and(|a| a.is_credit_account()) {
+            result.amount = 0.0 - result.amount;
+        }
+        result
+    }
+
+    fn print_row(&self, name: &str, converted: &Converted) {
+        let mut line = format!(
+            "{:<60} {:>15.2} {}",
+            name, converted.amount, self.currency.mnemonic
+        );
+        if !converted.missing.is_empty() {
+            let missing: Vec<_> = converted.missing.iter().map(|c| c.as_str()).collect();
+            line.push_str(&format!(" (without {}, no price)", missing.join(", ")));
+        }
+        println!("{}", line);
+    }
+
+    pub fn display(
+        &self,
+        tree: &AccountTree,
+        balances: &AccountBalances,
+        prices: &PriceTable,
+    ) -> usize {
+        println!(
+            "Balance sheet at {} in {}",
+            self.date, self.currency.mnemonic
+        );
+        let mut count = 0;
+        let mut totals = Vec::new();
+        for (section, title) in [
+            (Section::Assets, "Assets"),
+            (Section::Liabilities, "Liabilities"),
+            (Section::Equity, "Equity"),
+        ] {
+            println!();
+            let mut total = Converted::default();
+            for (account, depth) in tree.walk(&[], None) {
+                if section_of(account) != Some(section) {
+                    continue;
+                }
+                let converted = self.convert(tree, balances, prices, &tree.subtree(&account.guid));
+                let is_top = account
+                    .parent_guid
+                    .as_ref()
+                    .and_then(|parent| tree.get(parent))
+                    .is_none_or(|parent| section_of(parent) != Some(section));
+                if is_top {
+                    total.amount += converted.amount;
+                    total.missing.extend(converted.missing.iter().cloned());
+                }
+                if (converted.amount == 0.0 && converted.missing.is_empty())
+                    || self.max_depth.is_some_and(|max| depth > max)
+                {
+                    continue;
+                }
+                let indent = "  ".repeat(depth.saturating_sub(1));
+                let name = tree.full_name(&account.guid).unwrap_or_default();
+                self.print_row(&format!("{}{}", indent, name), &converted);
+                count += 1;
+            }
+            if section == Section::Equity {
+                let earnings = self.retained_earnings(tree, balances, prices);
+                self.print_row("Retained earnings", &earnings);
+                total.amount += earnings.amount;
+                total.missing.extend(earnings.missing);
+            }
+            self.print_row(&format!("Total {}", title.to_lowercase()), &total);
+            totals.push(total.amount);
+        }
+        println!();
+        println!(
+            "{:<60} {:>15.2} {}",
+            "Assets - liabilities - equity",
+            totals[0] - totals[1] - totals[2],
+            self.currency.mnemonic
+        );
+        count
+    }
+
+    // The incomes minus the expenses, which are not closed to an equity account yet
+    fn retained_earnings(
+        &self,
+        tree: &AccountTree,
+        balances: &AccountBalances,
+        prices: &PriceTable,
+    ) -> Converted {
+        let accounts: Vec<&Account> = tree
+            .walk(&[], None)
+            .into_iter()
+            .map(|(account, _)| account)
+            .filter(|account| matches!(account.account_type.as_str(), "INCOME" | "EXPENSE"))
+            .collect();
+        let mut result = Converted::default();
+        for account in accounts {
+            let converted = self.convert(tree, balances, prices, &[account]);
+            // the incomes are already negated as credit accounts
+            if account.is_credit_account() {
+                result.amount += converted.amount;
+            } else {
+                result.amount -= converted.amount;
+            }
+            result.missing.extend(converted.missing);
+        }
+        result
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use chrono::{Datelike, Duration, Months, NaiveDate};
+use diesel::prelude::*;
+
+use crate::models::{Account, Budget, BudgetAmount, Recurrence};
+use crate::query::account_tree::AccountTree;
+use crate::query::balances::AccountBalances;
+use crate::query::text_match::{filter_text, TextMatcher};
+use crate::reports::Period;
+
+// A GnuCash budget, with the amounts in the same sign as the splits
+pub struct BudgetPlan {
+    pub budget: Budget,
+    recurrence: Recurrence,
+    // account guid -> period number -> amount
+    amounts: HashMap<String, HashMap<i32, f64>>,
+}
+
+impl BudgetPlan {
+    pub fn load(
+        connection: &mut SqliteConnection,
+        name_filter: &Option<String>,
+        matcher: TextMatcher,
+    ) -> Result<Self> {
+        use crate::schema::{budget_amounts, budgets, recurrences};
+
+        let mut query = budgets::table.into_boxed();
+        if let Some(ref name_txt) = name_filter {
+            query = filter_text!(query, budgets::name, matcher, name_txt);
+        }
+        let mut found = query
+            .load::<Budget>(connection)
+            .expect("Error loading budgets");
+        if found.len() != 1 {
+            let names: Vec<_> = found.iter().map(|budget| budget.name.as_str()).collect();
+            return Err(anyhow!(
+                "The budget filter should pick only one budget, found: [{}]",
+                names.join(", ")
+            ));
+        }
+        let budget = found.pop().expect("One budget");
+        let recurrence = recurrences::table
+            .filter(recurrences::obj_guid.eq(&budget.guid))
+            .first::<Recurrence>(connection)
+            .optional()
+            .expect("Error loading recurrences")
+            .ok_or_else(|| anyhow!("The budget '{}' has no recurrence!", budget.name))?;
+        let mut amounts: HashMap<String, HashMap<i32, f64>> = HashMap::new();
+        for amount in budget_amounts::table
+            .filter(budget_amounts::budget_guid.eq(&budget.guid))
+            .load::<BudgetAmount>(connection)
+            .expect("Error loading budget amounts")
+        {
+            if amount.amount_denom != 0 {
+                amounts.entry(amount.account_guid).or_default().insert(
+                    amount.period_num,
+                    amount.amount_num as f64 / amount.amount_denom as f64,
+                );
+            }
+        }
+        let plan = BudgetPlan {
+            budget,
+            recurrence,
+            amounts,
+        };
+        // fail early on the unsupported recurrences
+        plan.period(0)?;
+        Ok(plan)
+    }
+
+    fn start_of(&self, period_num: i32) -> Result<NaiveDate> {
+        let text = &self.recurrence.recurrence_period_start;
+        let start = NaiveDate::parse_from_str(text, "%Y%m%d")
+            .or_else(|_| NaiveDate::parse_from_str(text, "%Y-%m-%d"))
+            .map_err(|_| anyhow!("Invalid budget start date: '{}'", text))?;
+        let steps = (period_num * self.recurrence.recurrence_mult) as u32;
+        match self.recurrence.recurrence_period_type.as_str() {
+            "day" => Ok(start + Duration::days(i64::from(steps))),
+            "week" => Ok(start + Duration::weeks(i64::from(steps))),
+            "month" => Ok(start + Months::new(steps)),
+            // the periods start on the last day of the months
+            "end of month" => {
+                let first = start.with_day(1).expect("First day of month");
+                Ok(first + Months::new(steps + 1) - Duration::days(1))
+            }
+            "year" => Ok(start + Months::new(steps * 12)),
+            other => Err(anyhow!("Unsupported budget period type: '{}'", other)),
+        }
+    }
+
+    // The dates of the period, numbered from zero
+    pub fn period(&self, period_num: i32) -> Result<Period> {
+        let next = self.start_of(period_num + 1)?;
+        Ok(Period::new(
+            self.start_of(period_num)?,
+            next - Duration::days(1),
+        ))
+    }
+
+    pub fn period_at(&self, date: NaiveDate) -> Result<Option<i32>> {
+        for period_num in 0..self.budget.num_periods {
+            let period = self.period(period_num)?;
+            if period.from <= date && date <= period.to {
+                return Ok(Some(period_num));
+            }
+        }
+        Ok(None)
+    }
+
+    fn amount(&self, account_guid: &str, period_num: i32) -> Option<f64> {
+        self.amounts.get(account_guid)?.get(&period_num).copied()
+    }
+
+    pub fn display_period(
+        &self,
+        connection: &mut SqliteConnection,
+        tree: &AccountTree,
+        period_num: i32,
+    ) -> Result<usize> {
+        let period = self.period(period_num)?;
+        let balances = AccountBalances::load(connection, Some(period.from), Some(period.to));
+        println!(
+            "{} - period {} of {}: {} - {}",
+            self.budget.name,
+            period_num + 1,
+            self.budget.num_periods,
+            period.from,
+            period.to
+        );
+        println!(
+            "{:<50} {:>14} {:>14} {:>14} {:>8}",
+            "Account", "Budget", "Actual", "Remaining", "Used"
+        );
+        let mut count = 0;
+        for (account, _) in tree.walk(&[], None) {
+            let budgeted = match self.amount(&account.guid, period_num) {
+                Some(amount) => amount,
+                None => continue,
+            };
+            let actual = balances
+                .by_commodity(tree, account, true)
+                .get(tree.commodity(account))
+                .map_or(0.0, |balance| balance.total);
+            let (budgeted, actual) = shown_amounts(account, budgeted, actual);
+            let used = if budgeted != 0.0 {
+                format!("{:.1}%", actual / budgeted * 100.0)
+            } else {
+                "-".to_owned()
+            };
+            println!(
+                "{:<50} {:>14.2} {:>14.2} {:>14.2} {:>8}",
+                tree.full_name(&account.guid).unwrap_or_default(),
+                budgeted,
+                actual,
+                budgeted - actual,
+                used
+            );
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+// The budgeted and the actual amounts as displayed, incomes are budgeted and shown
+// as positive numbers
+fn shown_amounts(account: &Account, budgeted: f64, actual: f64) -> (f64, f64) {
+    if account.is_credit_account() {
+        (0.0 - budgeted, 0.0 - actual)
+    } else {
+        (budgeted, actual)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan(period_type: &str, mult: i32) -> BudgetPlan {
+        BudgetPlan {
+            budget: Budget {
+                guid: "budget".to_owned(),
+                name: "Test".to_owned(),
+                description: None,
+                num_periods: 12,
+            },
+            recurrence: Recurrence {
+                id: 1,
+                obj_guid: "budget".to_owned(),
+                recurrence_mult: mult,
+                recurrence_period_type: period_type.to_owned(),
+                recurrence_period_start: "20230101".to_owned(),
+                recurrence_weekend_adjust: "none".to_owned(),
+            },
+            amounts: HashMap::new(),
+        }
+    }
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn test_budget_periods() {
+        let monthly = plan("month", 1);
+        assert_eq!(
+            monthly.period(1).unwrap(),
+            Period::new(date(2023, 2, 1), date(2023, 2, 28))
+        );
+        assert_eq!(monthly.period_at(date(2023, 12, 31)).unwrap(), Some(11));
+        assert_eq!(monthly.period_at(date(2024, 1, 1)).unwrap(), None);
+        let quarterly = plan("month", 3);
+        assert_eq!(
+            quarterly.period(1).unwrap(),
+            Period::new(date(2023, 4, 1), date(2023, 6, 30))
+        );
+        assert!(plan("nth weekday", 1).period(0).is_err());
+    }
+
+    #[test]
+    fn test_end_of_month_periods() {
+        let mut monthly = plan("end of month", 1);
+        monthly.recurrence.recurrence_period_start = "20230131".to_owned();
+        assert_eq!(
+            monthly.period(0).unwrap(),
+            Period::new(date(2023, 1, 31), date(2023, 2, 27))
+        );
+        assert_eq!(
+            monthly.period(1).unwrap(),
+            Period::new(date(2023, 2, 28), date(2023, 3, 30))
+        );
+    }
+
+    #[test]
+    fn test_income_amounts_are_positive() {
+        let account = |account_type: &str| Account {
+            guid: "account".to_owned(),
+            name: "Account".to_owned(),
+            account_type: account_type.to_owned(),
+            commodity_guid: None,
+            commodity_scu: 100,
+            non_std_scu: 0,
+            parent_guid: None,
+            code: None,
+            description: None,
+            hidden: None,
+            placeholder: None,
+        };
+        // the salary is a credit in the book, both in the budget and in the splits
+        let (budgeted, actual) = shown_amounts(&account("INCOME"), -1000.0, -800.0);
+        assert_eq!((budgeted, actual), (1000.0, 800.0));
+        assert_eq!(budgeted - actual, 200.0);
+        assert_eq!(
+            shown_amounts(&account("EXPENSE"), 300.0, 450.0),
+            (300.0, 450.0)
+        );
+    }
+}
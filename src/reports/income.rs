@@ -0,0 +1,161 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use diesel::prelude::*;
+
+use crate::models::Account;
+use crate::query::account_tree::AccountTree;
+use crate::query::balances::AccountBalances;
+use crate::reports::Period;
+
+fn is_income_or_expense(account: &Account) -> bool {
+    matches!(account.account_type.as_str(), "INCOME" | "EXPENSE")
+}
+
+// Incomes are shown as positive, like in GnuCash
+fn shown_amount(account_type: &str, amount: f64) -> f64 {
+    if account_type == "INCOME" {
+        0.0 - amount
+    } else {
+        amount
+    }
+}
+
+struct Column {
+    title: String,
+    balances: AccountBalances,
+}
+
+pub struct IncomeReport {
+    pub period: Period,
+    pub compare: bool,
+    pub monthly: bool,
+    pub max_depth: Option<usize>,
+}
+
+impl IncomeReport {
+    fn columns(&self, connection: &mut SqliteConnection) -> Vec<Column> {
+        let mut periods = Vec::new();
+        if self.monthly {
+            for month in self.period.months() {
+                periods.push((month.from.format("%Y-%m").to_string(), month));
+            }
+        }
+        periods.push(("Total".to_owned(), self.period));
+        if self.compare {
+            periods.push(("Previous".to_owned(), self.period.previous()));
+        }
+        periods
+            .into_iter()
+            .map(|(title, period)| Column {
+                title,
+                balances: AccountBalances::load(connection, Some(period.from), Some(period.to)),
+            })
+            .collect()
+    }
+
+    fn print_row(&self, name: &str, values: &[f64]) {
+        let mut line = format!("{:<50}", name);
+        for value in values {
+            line.push_str(&format!(" {:>14.2}", value));
+        }
+        if self.compare {
+            let total = values[values.len() - 2];
+            let previous = values[values.len() - 1];
+            line.push_str(&format!(" {:>14.2}", total - previous));
+        }
+        println!("{}", line);
+    }
+
+    pub fn display(&self, connection: &mut SqliteConnection, tree: &AccountTree) -> usize {
+        let columns = self.columns(connection);
+        println!(
+            "Income and expenses between {} and {}",
+            self.period.from, self.period.to
+        );
+        let mut header = format!("{:<50}", "Account");
+        for column in &columns {
+            header.push_str(&format!(" {:>14}", column.title));
+        }
+        if self.compare {
+            header.push_str(&format!(" {:>14}", "Change"));
+        }
+        println!("{}", header);
+
+        let mut count = 0;
+        // account type -> commodity -> total for every column
+        let mut totals: BTreeMap<&str, BTreeMap<String, Vec<f64>>> = BTreeMap::new();
+        for (account, depth) in tree.walk(&[], None) {
+            if !is_income_or_expense(account) {
+                continue;
+            }
+            let per_column: Vec<_> = columns
+                .iter()
+                .map(|column| column.balances.by_commodity(tree, account, true))
+                .collect();
+            let commodities: BTreeSet<&String> = per_column
+                .iter()
+                .flat_map(|balances| balances.keys())
+                .collect();
+            let is_top = account
+                .parent_guid
+                .as_ref()
+                .and_then(|parent| tree.get(parent))
+                .is_none_or(|parent| !is_income_or_expense(parent));
+            let indent = "  ".repeat(depth.saturating_sub(1));
+            let name = format!(
+                "{}{}",
+                indent,
+                tree.full_name(&account.guid).unwrap_or_default()
+            );
+            for commodity in commodities {
+                let values: Vec<f64> = per_column
+                    .iter()
+                    .map(|balances| {
+                        let amount = balances.get(commodity).map_or(0.0, |b| b.total);
+                        shown_amount(&account.account_type, amount)
+                    })
+                    .collect();
+                if values.iter().all(|value| *value == 0.0) {
+                    continue;
+                }
+                if is_top {
+                    let total = totals
+                        .entry(account.account_type.as_str())
+                        .or_default()
+                        .entry(commodity.clone())
+                        .or_insert_with(|| vec![0.0; columns.len()]);
+                    for (sum, value) in total.iter_mut().zip(&values) {
+                        *sum += value;
+                    }
+                }
+                if self.max_depth.is_none_or(|max| depth <= max) {
+                    self.print_row(&format!("{} {}", name, commodity), &values);
+                    count += 1;
+                }
+            }
+        }
+        self.display_totals(&totals, columns.len());
+        count
+    }
+
+    fn display_totals(&self, totals: &BTreeMap<&str, BTreeMap<String, Vec<f64>>>, size: usize) {
+        let empty = BTreeMap::new();
+        let incomes = totals.get("INCOME").unwrap_or(&empty);
+        let expenses = totals.get("EXPENSE").unwrap_or(&empty);
+        println!();
+        for (commodity, values) in incomes {
+            self.print_row(&format!("Total income {}", commodity), values);
+        }
+        for (commodity, values) in expenses {
+            self.print_row(&format!("Total expenses {}", commodity), values);
+        }
+        let commodities: BTreeSet<&String> = incomes.keys().chain(expenses.keys()).collect();
+        let zeros = vec![0.0; size];
+        for commodity in commodities {
+            let income = incomes.get(commodity).unwrap_or(&zeros);
+            let expense = expenses.get(commodity).unwrap_or(&zeros);
+            let net: Vec<f64> = income.iter().zip(expense).map(|(i, e)| i - e).collect();
+            self.print_row(&format!("Net income {}", commodity), &net);
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use chrono::{Datelike, Duration, Months, NaiveDate};
+
+pub mod balance_sheet;
+pub mod budget;
+pub mod income;
+pub mod reconciliation;
+pub mod series;
+
+// A closed date interval, both ends are included
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Period {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+}
+
+impl Period {
+    pub fn new(from: NaiveDate, to: NaiveDate) -> Self {
+        Period { from, to }
+    }
+
+    fn is_whole_months(&self) -> bool {
+        self.from.day() == 1 && (self.to + Duration::days(1)).day() == 1
+    }
+
+    // The same length of time right before this period. Whole months are shifted by
+    // months, so March is compared to February, and not to the last 31 days.
+    pub fn previous(&self) -> Period {
+        if self.is_whole_months() {
+            let months = (self.to.year() - self.from.year()) as u32 * 12 + self.to.month()
+                - self.from.month()
+                + 1;
+            let from = self.from - Months::new(months);
+            Period::new(from, self.from - Duration::days(1))
+        } else {
+            let length = self.to - self.from;
+            let to = self.from - Duration::days(1);
+            Period::new(to - length, to)
+        }
+    }
+
+    // The calendar months overlapping the period, cut to the period
+    pub fn months(&self) -> Vec<Period> {
+        self.split_by(|start| start.with_day(1).expect("First day of month") + Months::new(1))
+    }
+
+    // The weeks from Monday to Sunday overlapping the period, cut to the period
+    pub fn weeks(&self) -> Vec<Period> {
+        self.split_by(|start| {
+            start + Duration::days(7 - i64::from(start.weekday().num_days_from_monday()))
+        })
+    }
+
+    pub fn days(&self) -> Vec<Period> {
+        self.split_by(|start| start + Duration::days(1))
+    }
+
+    fn split_by(&self, next_start: impl Fn(NaiveDate) -> NaiveDate) -> Vec<Period> {
+        let mut result = Vec::new();
+        let mut start = self.from;
+        while start <= self.to {
+            let next = next_start(start);
+            result.push(Period::new(start, (next - Duration::days(1)).min(self.to)));
+            start = next;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn test_previous_period() {
+        let march = Period::new(date(2023, 3, 1), date(2023, 3, 31));
+        assert_eq!(
+            march.previous(),
+            Period::new(date(2023, 2, 1), date(2023, 2, 28))
+        );
+        let first_quarter = Period::new(date(2023, 1, 1), date(2023, 3, 31));
+        assert_eq!(
+            first_quarter.previous(),
+            Period::new(date(2022, 10, 1), date(2022, 12, 31))
+        );
+        let ten_days = Period::new(date(2023, 3, 11), date(2023, 3, 20));
+        assert_eq!(
+            ten_days.previous(),
+            Period::new(date(2023, 3, 1), date(2023, 3, 10))
+        );
+    }
+
+    #[test]
+    fn test_weeks() {
+        // 2023-03-01 is a Wednesday
+        let period = Period::new(date(2023, 3, 1), date(2023, 3, 14));
+        assert_eq!(
+            period.weeks(),
+            vec![
+                Period::new(date(2023, 3, 1), date(2023, 3, 5)),
+                Period::new(date(2023, 3, 6), date(2023, 3, 12)),
+                Period::new(date(2023, 3, 13), date(2023, 3, 14)),
+            ]
+        );
+        assert_eq!(period.days().len(), 14);
+    }
+
+    #[test]
+    fn test_months() {
+        let period = Period::new(date(2023, 1, 15), date(2023, 3, 10));
+        assert_eq!(
+            period.months(),
+            vec![
+                Period::new(date(2023, 1, 15), date(2023, 1, 31)),
+                Period::new(date(2023, 2, 1), date(2023, 2, 28)),
+                Period::new(date(2023, 3, 1), date(2023, 3, 10)),
+            ]
+        );
+    }
+}
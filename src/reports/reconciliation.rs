@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+
+use chrono::{Duration, NaiveDate};
+use console::style;
+use diesel::prelude::*;
+
+use crate::models::Account;
+use crate::query::account_tree::AccountTree;
+use crate::utils::parse_sqlite_date;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct AccountReconciliation {
+    pub last_reconciled: Option<NaiveDate>,
+    pub reconciled: f64,
+    // Not cleared splits, posted before the cutoff date
+    pub old_uncleared: usize,
+}
+
+impl AccountReconciliation {
+    // The splits reconciled after the date of the summary are left out, the frozen ones are
+    // reconciled too
+    fn add_split(
+        &mut self,
+        reconcile_state: &str,
+        reconcile_date: Option<NaiveDate>,
+        post_date: Option<NaiveDate>,
+        quantity: f64,
+        date: NaiveDate,
+        cutoff: NaiveDate,
+    ) {
+        match reconcile_state {
+            "y" | "f" if reconcile_date.is_some_and(|reconciled| reconciled > date) => {}
+            "y" | "f" => {
+                self.reconciled += quantity;
+                if reconcile_date > self.last_reconciled {
+                    self.last_reconciled = reconcile_date;
+                }
+            }
+            "n" if post_date.is_some_and(|date| date < cutoff) => self.old_uncleared += 1,
+            _ => {}
+        }
+    }
+}
+
+// The state of the reconciliation of the accounts at a day, to see which ones need attention
+pub struct ReconciliationSummary {
+    pub date: NaiveDate,
+    // Splits which are not cleared after this many days are counted
+    pub older_than_days: i64,
+}
+
+impl ReconciliationSummary {
+    fn cutoff(&self) -> NaiveDate {
+        self.date - Duration::days(self.older_than_days)
+    }
+
+    fn load(&self, connection: &mut SqliteConnection) -> HashMap<String, AccountReconciliation> {
+        use crate::schema::{splits, transactions};
+
+        let rows = splits::table
+            .inner_join(transactions::table)
+            .select((
+                splits::account_guid,
+                splits::reconcile_state,
+                splits::reconcile_date,
+                transactions::post_date,
+                splits::quantity_num,
+                splits::quantity_denom,
+            ))
+            .load::<(String, String, Option<String>, Option<String>, i64, i64)>(connection)
+            .expect("Error loading splits");
+
+        let cutoff = self.cutoff();
+        let mut result: HashMap<String, AccountReconciliation> = HashMap::new();
+        for (account_guid, state, reconcile_date, post_date, quantity_num, quantity_denom) in rows {
+            let post_date = parse_sqlite_date(&post_date).map(|date| date.date());
+            if post_date.is_some_and(|date| date > self.date) {
+                continue;
+            }
+            let quantity = if quantity_denom == 0 {
+                0.0
+            } else {
+                quantity_num as f64 / quantity_denom as f64
+            };
+            result.entry(account_guid).or_default().add_split(
+                &state,
+                parse_sqlite_date(&reconcile_date).map(|date| date.date()),
+                post_date,
+                quantity,
+                self.date,
+                cutoff,
+            );
+        }
+        result
+    }
+
+    // Lists the accounts with splits, under the given ones, or in the whole book
+    pub fn display(
+        &self,
+        connection: &mut SqliteConnection,
+        tree: &AccountTree,
+        roots: &[&Account],
+    ) -> usize {
+        let summaries = self.load(connection);
+        println!(
+            "{:<50} {:>15} {:>20} {:>12}",
+            "Account",
+            "Last reconciled",
+            "Reconciled",
+            format!("Old ({}d+)", self.older_than_days)
+        );
+        let mut count = 0;
+        for (account, _) in tree.walk(roots, None) {
+            let summary = match summaries.get(&account.guid) {
+                Some(summary) => summary,
+                None => continue,
+            };
+            let last_reconciled = summary
+                .last_reconciled
+                .map_or_else(|| "never".to_owned(), |date| date.to_string());
+            let old_uncleared = format!("{:>12}", summary.old_uncleared);
+            println!(
+                "{:<50} {:>15} {:>20} {}",
+                tree.full_name(&account.guid).unwrap_or_default(),
+                last_reconciled,
+                tree.format_amount(account, summary.reconciled),
+                if summary.old_uncleared > 0 {
+                    style(old_uncleared).red()
+                } else {
+                    style(old_uncleared)
+                }
+            );
+            count += 1;
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_split() {
+        let date = |day| NaiveDate::from_ymd_opt(2023, 3, day);
+        let (today, cutoff) = (date(20).unwrap(), date(10).unwrap());
+        let mut summary = AccountReconciliation::default();
+        summary.add_split("y", date(5), date(1), 100.0, today, cutoff);
+        summary.add_split("y", date(3), date(2), 50.0, today, cutoff);
+        summary.add_split("c", None, date(4), 20.0, today, cutoff);
+        summary.add_split("n", None, date(9), 10.0, today, cutoff);
+        summary.add_split("n", None, date(10), 10.0, today, cutoff);
+        assert_eq!(
+            summary,
+            AccountReconciliation {
+                last_reconciled: date(5),
+                reconciled: 150.0,
+                old_uncleared: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_later_reconciliations_are_ignored() {
+        let date = |day| NaiveDate::from_ymd_opt(2023, 3, day);
+        let (today, cutoff) = (date(20).unwrap(), date(10).unwrap());
+        let mut summary = AccountReconciliation::default();
+        summary.add_split("y", date(20), date(1), 100.0, today, cutoff);
+        summary.add_split("y", date(25), date(2), 50.0, today, cutoff);
+        summary.add_split("f", date(15), date(3), 30.0, today, cutoff);
+        summary.add_split("f", date(21), date(4), 5.0, today, cutoff);
+        assert_eq!(summary.last_reconciled, date(20));
+        assert_eq!(summary.reconciled, 130.0);
+    }
+}
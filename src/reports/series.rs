@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use clap::ValueEnum;
+use diesel::prelude::*;
+use serde_json::{Map, Value};
+
+use crate::models::Account;
+use crate::query::account_tree::AccountTree;
+use crate::reports::Period;
+use crate::utils::{format_sqlite_date, parse_sqlite_date};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Interval {
+    Day,
+    Week,
+    Month,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SeriesValue {
+    // The sum of the splits within the period
+    Flow,
+    // The balance at the end of the period
+    Balance,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Csv,
+    Json,
+}
+
+// One column of the series, an account, optionally with the accounts under it
+// in the same commodity.
+struct SeriesColumn {
+    name: String,
+    guids: Vec<String>,
+    sign: f64,
+}
+
+pub struct Series {
+    pub period: Period,
+    pub interval: Interval,
+    pub value: SeriesValue,
+    pub rollup: bool,
+    pub output: OutputFormat,
+}
+
+impl Series {
+    fn columns(&self, tree: &AccountTree, accounts: &[&Account]) -> Vec<SeriesColumn> {
+        accounts
+            .iter()
+            .map(|account| {
+                let guids = if self.rollup {
+                    tree.subtree(&account.guid)
+                        .into_iter()
+                        .filter(|child| child.commodity_guid == account.commodity_guid)
+                        .map(|child| child.guid.clone())
+                        .collect()
+                } else {
+                    vec![account.guid.clone()]
+                };
+                SeriesColumn {
+                    name: tree
+                        .full_name(&account.guid)
+                        .unwrap_or_else(|| account.name.clone()),
+                    guids,
+                    sign: if account.is_credit_account() {
+                        -1.0
+                    } else {
+                        1.0
+                    },
+                }
+            })
+            .collect()
+    }
+
+    fn buckets(&self) -> Vec<Period> {
+        match self.interval {
+            Interval::Day => self.period.days(),
+            Interval::Week => self.period.weeks(),
+            Interval::Month => self.period.months(),
+        }
+    }
+
+    // Rows of the period start, and the value of every column
+    pub fn compute(
+        &self,
+        connection: &mut SqliteConnection,
+        tree: &AccountTree,
+        accounts: &[&Account],
+    ) -> (Vec<String>, Vec<(Period, Vec<f64>)>) {
+        use crate::schema::{splits, transactions};
+
+        let columns = self.columns(tree, accounts);
+        let mut column_of: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (idx, column) in columns.iter().enumerate() {
+            for guid in &column.guids {
+                column_of.entry(guid.as_str()).or_default().push(idx);
+            }
+        }
+        let to_txt = format_sqlite_date(
+            &self
+                .period
+                .to
+                .and_hms_opt(23, 59, 59)
+                .expect("Correct date"),
+        );
+        let mut query = splits::table
+            .inner_join(transactions::table)
+            .filter(splits::account_guid.eq_any(column_of.keys().copied().collect::<Vec<_>>()))
+            .filter(transactions::post_date.le(to_txt))
+            .select((
+                splits::account_guid,
+                transactions::post_date,
+                splits::quantity_num,
+                splits::quantity_denom,
+            ))
+            .into_boxed();
+        // the balances need the splits before the period too
+        if self.value == SeriesValue::Flow {
+            let from_txt =
+                format_sqlite_date(&self.period.from.and_hms_opt(0, 0, 0).expect("Correct date"));
+            query = query.filter(transactions::post_date.ge(from_txt));
+        }
+        let rows = query
+            .load::<(String, Option<String>, i64, i64)>(connection)
+            .expect("Error loading splits");
+
+        let buckets = self.buckets();
+        let mut opening = vec![0.0; columns.len()];
+        let mut flows = vec![vec![0.0; columns.len()]; buckets.len()];
+        for (account_guid, post_date, quantity_num, quantity_denom) in rows {
+            let date = match parse_sqlite_date(&post_date) {
+                Some(date_time) => date_time.date(),
+                None => continue,
+            };
+            if quantity_denom == 0 {
+                continue;
+            }
+            let quantity = quantity_num as f64 / quantity_denom as f64;
+            let target = if date < self.period.from {
+                &mut opening
+            } else {
+                match buckets.iter().position(|bucket| date <= bucket.to) {
+                    Some(idx) => &mut flows[idx],
+                    None => continue,
+                }
+            };
+            for idx in column_of.get(account_guid.as_str()).into_iter().flatten() {
+                target[*idx] += quantity * columns[*idx].sign;
+            }
+        }
+        if self.value == SeriesValue::Balance {
+            let mut running = opening;
+            for flow in flows.iter_mut() {
+                for (balance, value) in running.iter_mut().zip(flow.iter_mut()) {
+                    *balance += *value;
+                    *value = *balance;
+                }
+            }
+        }
+        let names = columns.into_iter().map(|column| column.name).collect();
+        (names, buckets.into_iter().zip(flows).collect())
+    }
+
+    pub fn write(&self, names: &[String], rows: &[(Period, Vec<f64>)]) -> Result<String> {
+        match self.output {
+            OutputFormat::Csv => {
+                let mut result = String::new();
+                let header: Vec<String> = names.iter().map(|name| csv_field(name)).collect();
+                result.push_str(&format!("period,{}\n", header.join(",")));
+                for (period, values) in rows {
+                    let cells: Vec<String> = values.iter().map(|v| format!("{:.2}", v)).collect();
+                    result.push_str(&format!("{},{}\n", period.from, cells.join(",")));
+                }
+                Ok(result)
+            }
+            OutputFormat::Json => {
+                let json_rows: Vec<Value> = rows
+                    .iter()
+                    .map(|(period, values)| {
+                        let mut row = Map::new();
+                        row.insert("period".to_owned(), Value::from(period.from.to_string()));
+                        for (name, value) in names.iter().zip(values) {
+                            row.insert(name.clone(), Value::from((value * 100.0).round() / 100.0));
+                        }
+                        Value::Object(row)
+                    })
+                    .collect();
+                Ok(serde_json::to_string_pretty(&json_rows)? + "\n")
+            }
+        }
+    }
+}
+
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+    use crate::query::test_book;
+
+    fn account(guid: &str, parent: &str, account_type: &str) -> (String, Account) {
+        let account = Account {
+            guid: guid.to_owned(),
+            name: guid.to_owned(),
+            account_type: account_type.to_owned(),
+            commodity_guid: Some("huf".to_owned()),
+            commodity_scu: 100,
+            non_std_scu: 0,
+            parent_guid: Some(parent.to_owned()),
+            code: None,
+            description: None,
+            hidden: None,
+            placeholder: None,
+        };
+        (guid.to_owned(), account)
+    }
+
+    fn book() -> SqliteConnection {
+        let mut connection = test_book::empty_book();
+        let splits = [
+            ("groceries", "2022-12-20", 4000),
+            ("groceries", "2023-01-05", 10000),
+            ("food", "2023-02-10", 5000),
+            ("salary", "2023-01-31", -100000),
+        ];
+        for (idx, (account, date, quantity)) in splits.iter().enumerate() {
+            test_book::add_split(&mut connection, idx, account, date, *quantity, *quantity);
+        }
+        connection
+    }
+
+    #[test]
+    fn test_compute() {
+        let tree = AccountTree::new(
+            "root".to_owned(),
+            vec![
+                account("food", "root", "EXPENSE"),
+                account("groceries", "food", "EXPENSE"),
+                account("salary", "root", "INCOME"),
+            ]
+            .into_iter()
+            .collect(),
+            HashMap::new(),
+        );
+        let accounts: Vec<&Account> = ["food", "salary"]
+            .iter()
+            .filter_map(|guid| tree.get(guid))
+            .collect();
+        let date = |month, day| NaiveDate::from_ymd_opt(2023, month, day).unwrap();
+        let mut series = Series {
+            period: Period::new(date(1, 1), date(2, 28)),
+            interval: Interval::Month,
+            value: SeriesValue::Flow,
+            rollup: true,
+            output: OutputFormat::Csv,
+        };
+        let connection = &mut book();
+
+        let (names, rows) = series.compute(connection, &tree, &accounts);
+        assert_eq!(names, vec!["food", "salary"]);
+        let values: Vec<&Vec<f64>> = rows.iter().map(|(_, values)| values).collect();
+        assert_eq!(values, vec![&vec![100.0, 1000.0], &vec![50.0, 0.0]]);
+
+        series.value = SeriesValue::Balance;
+        let (_, rows) = series.compute(connection, &tree, &accounts);
+        let values: Vec<&Vec<f64>> = rows.iter().map(|(_, values)| values).collect();
+        assert_eq!(values, vec![&vec![140.0, 1000.0], &vec![190.0, 1000.0]]);
+
+        series.rollup = false;
+        let (_, rows) = series.compute(connection, &tree, &accounts);
+        assert_eq!(rows[1].1, vec![50.0, 1000.0]);
+    }
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field("Expenses:Food"), "Expenses:Food");
+        assert_eq!(csv_field("Food, Drinks"), "\"Food, Drinks\"");
+        assert_eq!(csv_field("The \"Best\" Bar"), "\"The \"\"Best\"\" Bar\"");
+    }
+}
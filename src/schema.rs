@@ -21,6 +21,26 @@ table! {
     }
 }
 
+table! {
+    budget_amounts (id) {
+        id -> Integer,
+        budget_guid -> Text,
+        account_guid -> Text,
+        period_num -> Integer,
+        amount_num -> BigInt,
+        amount_denom -> BigInt,
+    }
+}
+
+table! {
+    budgets (guid) {
+        guid -> Text,
+        name -> Text,
+        description -> Nullable<Text>,
+        num_periods -> Integer,
+    }
+}
+
 table! {
     commodities (guid) {
         guid -> Text,
@@ -82,6 +102,32 @@ table! {
         value_denom -> BigInt,
     }
 }
+table! {
+    recurrences (id) {
+        id -> Integer,
+        obj_guid -> Text,
+        recurrence_mult -> Integer,
+        recurrence_period_type -> Text,
+        recurrence_period_start -> Text,
+        recurrence_weekend_adjust -> Text,
+    }
+}
+table! {
+    slots (id) {
+        id -> Integer,
+        obj_guid -> Text,
+        name -> Text,
+        slot_type -> Integer,
+        int64_val -> Nullable<BigInt>,
+        string_val -> Nullable<Text>,
+        double_val -> Nullable<Double>,
+        timespec_val -> Nullable<Text>,
+        guid_val -> Nullable<Text>,
+        numeric_val_num -> Nullable<BigInt>,
+        numeric_val_denom -> Nullable<BigInt>,
+        gdate_val -> Nullable<Text>,
+    }
+}
 table! {
     splits (guid) {
         guid -> Text,
@@ -112,9 +158,13 @@ table! {
 allow_tables_to_appear_in_same_query!(
     accounts,
     books,
+    budget_amounts,
+    budgets,
     commodities,
     entries,
     prices,
+    recurrences,
+    slots,
     splits,
     transactions,
 );
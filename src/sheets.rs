@@ -1,58 +1,446 @@
-use calamine::DataType;
-use chrono::{NaiveDate, NaiveDateTime};
+use std::collections::HashMap;
 
-// Format yyyy.mm.dd.
-pub fn cell_to_date(cell: &DataType) -> Option<NaiveDate> {
-    cell_to_date_raw(cell, "%Y.%m.%d.")
+use anyhow::Result;
+use calamine::{DataType, Range};
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+
+// The header row is searched within the first rows, after the title and account details.
+const HEADER_SEARCH_ROWS: usize = 30;
+
+// A column of a sheet format, with the header texts it can appear under, and its
+// position in the exports which have no header row at all.
+pub struct Column {
+    pub name: &'static str,
+    pub headers: &'static [&'static str],
+    pub required: bool,
+    pub position: Option<usize>,
 }
 
-// Format yyyy-mm-dd
-pub fn cell_to_iso_date(cell: &DataType) -> Option<NaiveDate> {
-    cell_to_date_raw(cell, "%Y-%m-%d")
+impl Column {
+    pub const fn required(name: &'static str, headers: &'static [&'static str]) -> Self {
+        Column {
+            name,
+            headers,
+            required: true,
+            position: None,
+        }
+    }
+
+    pub const fn optional(name: &'static str, headers: &'static [&'static str]) -> Self {
+        Column {
+            name,
+            headers,
+            required: false,
+            position: None,
+        }
+    }
+
+    pub const fn at(self, position: usize) -> Self {
+        Column {
+            position: Some(position),
+            ..self
+        }
+    }
+
+    fn matches(&self, cell: &DataType) -> bool {
+        match cell {
+            DataType::String(text) => {
+                let normalized = normalize_header(text);
+                self.headers
+                    .iter()
+                    .any(|header| normalize_header(header) == normalized)
+            }
+            _ => false,
+        }
+    }
 }
 
-// Format dd.mm.yyyy
-pub fn cell_to_german_date(cell: &DataType) -> Option<NaiveDate> {
-    cell_to_date_raw(cell, "%d.%m.%Y")
+fn normalize_header(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+// Maps the column names of a format to the cell positions found in the header row,
+// or to the default positions of the format, when the sheet has no header row.
+#[derive(Debug)]
+pub struct ColumnBinding {
+    pub header_row: Option<usize>,
+    indexes: HashMap<&'static str, usize>,
 }
 
+impl ColumnBinding {
+    pub fn find(range: &Range<DataType>, columns: &[Column]) -> Result<ColumnBinding> {
+        let mut best: Option<ColumnBinding> = None;
+        for (row_idx, row) in range.rows().take(HEADER_SEARCH_ROWS).enumerate() {
+            let mut indexes = HashMap::new();
+            for column in columns {
+                if let Some(position) = row.iter().position(|cell| column.matches(cell)) {
+                    indexes.insert(column.name, position);
+                }
+            }
+            if best
+                .as_ref()
+                .is_none_or(|binding| indexes.len() > binding.indexes.len())
+            {
+                best = Some(ColumnBinding {
+                    header_row: Some(row_idx),
+                    indexes,
+                });
+            }
+        }
+        let binding = best.unwrap_or(ColumnBinding {
+            header_row: None,
+            indexes: HashMap::new(),
+        });
+        let missing: Vec<String> = columns
+            .iter()
+            .filter(|column| column.required && !binding.indexes.contains_key(column.name))
+            .map(|column| format!("{} ({})", column.name, column.headers.join("/")))
+            .collect();
+        if missing.is_empty() {
+            return Ok(binding);
+        }
+        // only a sheet without any known header is read by the default positions, a changed
+        // header could mean, that the columns moved too
+        let positional = if binding.indexes.is_empty() {
+            ColumnBinding::by_position(columns)
+        } else {
+            None
+        };
+        if let Some(positional) = positional {
+            Ok(positional)
+        } else {
+            Err(anyhow!(
+                "Missing required column(s) in the header row: {}",
+                missing.join(", ")
+            ))
+        }
+    }
+
+    // The default layout, if every required column has a default position
+    fn by_position(columns: &[Column]) -> Option<ColumnBinding> {
+        if columns
+            .iter()
+            .any(|column| column.required && column.position.is_none())
+        {
+            return None;
+        }
+        let indexes = columns
+            .iter()
+            .filter_map(|column| column.position.map(|position| (column.name, position)))
+            .collect();
+        Some(ColumnBinding {
+            header_row: None,
+            indexes,
+        })
+    }
+
+    // Without a header row, every row is data, the rest is filtered by the format
+    pub fn first_data_row(&self) -> usize {
+        self.header_row.map_or(0, |row| row + 1)
+    }
+
+    pub fn bind<'a>(&'a self, cells: &'a [DataType]) -> BoundRow<'a> {
+        BoundRow {
+            binding: self,
+            cells,
+        }
+    }
+}
+
+pub struct BoundRow<'a> {
+    binding: &'a ColumnBinding,
+    cells: &'a [DataType],
+}
+
+impl<'a> BoundRow<'a> {
+    // Unknown, optional and missing cells are all treated as empty.
+    pub fn get(&self, name: &str) -> &'a DataType {
+        self.binding
+            .indexes
+            .get(name)
+            .and_then(|idx| self.cells.get(*idx))
+            .unwrap_or(&DataType::Empty)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.iter().all(|cell| *cell == DataType::Empty)
+    }
+}
+
+// Format yyyy.mm.dd.
+pub const HUNGARIAN_DATE: &str = "%Y.%m.%d.";
+// Format yyyy.mm.dd. hh:mm:ss
+pub const HUNGARIAN_DATETIME: &str = "%Y.%m.%d. %H:%M:%S";
+// Format yyyy-mm-dd
+pub const ISO_DATE: &str = "%Y-%m-%d";
+// Format dd.mm.yyyy
+pub const GERMAN_DATE: &str = "%d.%m.%Y";
 // Format dd-mm-yyyy
-pub fn cell_to_english_date(cell: &DataType) -> Option<NaiveDate> {
-    cell_to_date_raw(cell, "%d-%m-%Y")
+pub const ENGLISH_DATE: &str = "%d-%m-%Y";
+
+// Converts cells to dates and amounts, whether the sheet stores them as real
+// dates and numbers, or as text written in the bank's own locale.
+#[derive(Debug, Clone)]
+pub struct CellConverter {
+    pub decimal_separator: char,
+    pub date_patterns: Vec<String>,
 }
 
-pub fn cell_to_date_raw(cell: &DataType, format: &str) -> Option<NaiveDate> {
-    if let DataType::String(str) = cell {
-        NaiveDate::parse_from_str(str, format).ok()
-    } else {
-        None
+impl CellConverter {
+    pub fn new(decimal_separator: char, date_patterns: &[&str]) -> Self {
+        CellConverter {
+            decimal_separator,
+            date_patterns: date_patterns.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+
+    // Patterns and separators given on the command line take precedence over the format's own.
+    pub fn with_overrides(
+        mut self,
+        decimal_separator: Option<char>,
+        date_patterns: &[String],
+    ) -> Self {
+        if let Some(separator) = decimal_separator {
+            self.decimal_separator = separator;
+        }
+        if !date_patterns.is_empty() {
+            self.date_patterns = date_patterns.to_vec();
+        }
+        self
+    }
+
+    pub fn parse_date(&self, cell: &DataType) -> Option<NaiveDate> {
+        self.parse_datetime(cell).map(|datetime| datetime.date())
+    }
+
+    pub fn parse_datetime(&self, cell: &DataType) -> Option<NaiveDateTime> {
+        match cell {
+            DataType::DateTime(serial) | DataType::Float(serial) => {
+                excel_serial_to_datetime(*serial)
+            }
+            DataType::Int(serial) => excel_serial_to_datetime(*serial as f64),
+            DataType::DateTimeIso(text) => parse_iso_datetime(text),
+            DataType::String(text) => self.parse_date_text(text.trim()),
+            _ => None,
+        }
+    }
+
+    fn parse_date_text(&self, text: &str) -> Option<NaiveDateTime> {
+        self.date_patterns.iter().find_map(|pattern| {
+            NaiveDateTime::parse_from_str(text, pattern)
+                .ok()
+                .or_else(|| {
+                    NaiveDate::parse_from_str(text, pattern)
+                        .ok()
+                        .and_then(|date| date.and_hms_opt(0, 0, 0))
+                })
+        })
+    }
+
+    pub fn parse_amount(&self, cell: &DataType) -> Option<f64> {
+        match cell {
+            DataType::Float(value) => Some(*value),
+            DataType::Int(value) => Some(*value as f64),
+            DataType::String(text) => parse_localized_number(text, self.decimal_separator),
+            _ => None,
+        }
     }
 }
 
-pub fn cell_to_datetime(cell: &DataType) -> Option<NaiveDateTime> {
-    if let DataType::String(str) = cell {
-        NaiveDateTime::parse_from_str(str, "%Y.%m.%d. %H:%M:%S").ok()
-    } else {
-        None
+// Excel counts days from 1899-12-30, and wrongly treats 1900 as a leap year.
+fn excel_serial_to_datetime(serial: f64) -> Option<NaiveDateTime> {
+    if !(1.0..2_958_466.0).contains(&serial) {
+        return None;
     }
+    let serial = if serial < 60.0 { serial + 1.0 } else { serial };
+    let epoch = NaiveDate::from_ymd_opt(1899, 12, 30)?.and_hms_opt(0, 0, 0)?;
+    let seconds = (serial * 86_400.0).round() as i64;
+    epoch.checked_add_signed(Duration::seconds(seconds))
 }
 
-pub fn cell_to_string(cell: &DataType) -> Option<String> {
-    if let DataType::String(str) = cell {
-        if str.is_empty() {
-            None
-        } else {
-            Some(str.clone())
+fn parse_iso_datetime(text: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S%.f")
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(text, ISO_DATE)
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+}
+
+// Parses amounts like '-1 234,56 Ft', '1.234,56 EUR' or '(12.00)'. Any other separator
+// is a thousands separator only between groups of three digits, so '12.50' is not
+// read as 1250 with ',' as the decimal separator, it is rejected as ambiguous.
+pub fn parse_localized_number(text: &str, decimal_separator: char) -> Option<f64> {
+    // the currency code or symbol can be on both sides of the sign
+    let currency = |c: char| c.is_alphabetic() || c.is_whitespace() || "€$£¥₣".contains(c);
+    let mut core = text.trim_matches(currency);
+    let mut negative = false;
+    if let Some(inner) = core
+        .strip_prefix('(')
+        .and_then(|rest| rest.strip_suffix(')'))
+    {
+        negative = true;
+        core = inner.trim_matches(currency);
+    }
+    if let Some(unsigned) = core.strip_prefix('-').or_else(|| core.strip_suffix('-')) {
+        negative = true;
+        core = unsigned;
+    } else if let Some(unsigned) = core.strip_prefix('+') {
+        core = unsigned;
+    }
+    let core = core.trim_matches(currency);
+    let (integer, fraction) = match core.split_once(decimal_separator) {
+        Some((integer, fraction)) => (integer, fraction),
+        None => (core, ""),
+    };
+    if !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let mut separators = integer.chars().filter(|c| !c.is_ascii_digit());
+    if let Some(separator) = separators.next() {
+        if !(separator == '.' || separator == ',' || separator == '\'' || separator.is_whitespace())
+            || separators.any(|other| other != separator)
+        {
+            return None;
+        }
+        let groups: Vec<&str> = integer.split(separator).collect();
+        if !(1..=3).contains(&groups[0].len()) || groups[1..].iter().any(|group| group.len() != 3) {
+            return None;
         }
-    } else {
-        None
     }
+    let digits: String = integer.chars().filter(|c| c.is_ascii_digit()).collect();
+    if digits.is_empty() {
+        return None;
+    }
+    let value: f64 = format!("{}.{}", digits, fraction).parse().ok()?;
+    Some(if negative { -value } else { value })
 }
 
-pub fn cell_to_float(cell: &DataType) -> Option<f64> {
-    if let DataType::Float(flt) = cell {
-        Some(*flt)
-    } else {
-        None
+pub fn cell_to_string(cell: &DataType) -> Option<String> {
+    match cell {
+        DataType::String(str) if !str.is_empty() => Some(str.clone()),
+        // account numbers are often stored as numbers
+        DataType::Int(value) => Some(value.to_string()),
+        DataType::Float(value) if value.fract() == 0.0 => Some(format!("{:.0}", value)),
+        DataType::DateTimeIso(str) => Some(str.clone()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLUMNS: &[Column] = &[
+        Column::required("date", &["Date", "Datum"]),
+        Column::required("amount", &["Amount", "Betrag"]),
+        Column::optional("note", &["Note"]),
+    ];
+
+    fn sheet(rows: &[&[&str]]) -> Range<DataType> {
+        let mut range = Range::new((0, 0), (rows.len() as u32 - 1, 3));
+        for (row_idx, row) in rows.iter().enumerate() {
+            for (col_idx, value) in row.iter().enumerate() {
+                range.set_value(
+                    (row_idx as u32, col_idx as u32),
+                    DataType::String(value.to_string()),
+                );
+            }
+        }
+        range
+    }
+
+    #[test]
+    fn test_bind_columns_by_header() {
+        let range = sheet(&[
+            &["Statement"],
+            &["Id", " betrag ", "Datum"],
+            &["1", "2", "3"],
+        ]);
+        let binding = ColumnBinding::find(&range, COLUMNS).unwrap();
+        assert_eq!(binding.header_row, Some(1));
+        let row = range.rows().nth(2).unwrap();
+        let bound = binding.bind(row);
+        assert_eq!(bound.get("amount"), &DataType::String("2".to_string()));
+        assert_eq!(bound.get("date"), &DataType::String("3".to_string()));
+        assert_eq!(bound.get("note"), &DataType::Empty);
+    }
+
+    #[test]
+    fn test_parse_localized_amounts() {
+        assert_eq!(parse_localized_number("-1 234,56 Ft", ','), Some(-1234.56));
+        assert_eq!(parse_localized_number("1.234,56 EUR", ','), Some(1234.56));
+        assert_eq!(parse_localized_number("USD 1,234.56", '.'), Some(1234.56));
+        assert_eq!(parse_localized_number("(12.00)", '.'), Some(-12.0));
+        assert_eq!(parse_localized_number("1,234.56", '.'), Some(1234.56));
+        assert_eq!(parse_localized_number("1'234'567", '.'), Some(1234567.0));
+        assert_eq!(parse_localized_number("12,00-", ','), Some(-12.0));
+        assert_eq!(parse_localized_number("EUR -1.234,56", ','), Some(-1234.56));
+        assert_eq!(parse_localized_number("USD -12.00", '.'), Some(-12.0));
+        assert_eq!(parse_localized_number("-12.00 USD", '.'), Some(-12.0));
+        assert_eq!(parse_localized_number("1 234,56- Ft", ','), Some(-1234.56));
+        assert_eq!(parse_localized_number("(EUR 12.00)", '.'), Some(-12.0));
+        assert_eq!(parse_localized_number("+12.00 €", '.'), Some(12.0));
+        assert_eq!(parse_localized_number("n/a", '.'), None);
+        assert_eq!(parse_localized_number("", '.'), None);
+    }
+
+    #[test]
+    fn test_ambiguous_grouping_is_rejected() {
+        assert_eq!(parse_localized_number("12.50", ','), None);
+        assert_eq!(parse_localized_number("1.234,56", ','), Some(1234.56));
+        assert_eq!(parse_localized_number("1,234.56", '.'), Some(1234.56));
+        assert_eq!(parse_localized_number("1,2345.00", '.'), None);
+        assert_eq!(parse_localized_number("1.234 567,00", ','), None);
+    }
+
+    #[test]
+    fn test_parse_date_cells() {
+        let converter = CellConverter::new(',', &[GERMAN_DATE, HUNGARIAN_DATETIME]);
+        let expected = NaiveDate::from_ymd_opt(2023, 1, 31);
+        assert_eq!(converter.parse_date(&DataType::DateTime(44957.0)), expected);
+        assert_eq!(
+            converter.parse_date(&DataType::DateTimeIso("2023-01-31T10:20:00".to_string())),
+            expected
+        );
+        assert_eq!(
+            converter.parse_date(&DataType::String("31.01.2023".to_string())),
+            expected
+        );
+        assert_eq!(
+            converter.parse_date(&DataType::String("2023.01.31. 10:20:00".to_string())),
+            expected
+        );
+        assert_eq!(converter.parse_date(&DataType::Empty), None);
+    }
+
+    #[test]
+    fn test_bind_columns_by_position() {
+        const POSITIONED: &[Column] = &[
+            Column::required("date", &["Date"]).at(2),
+            Column::required("amount", &["Amount"]).at(0),
+            Column::optional("note", &["Note"]),
+        ];
+        let range = sheet(&[&["12", "x", "2023-01-31"]]);
+        let binding = ColumnBinding::find(&range, POSITIONED).unwrap();
+        assert_eq!(binding.first_data_row(), 0);
+        let bound = binding.bind(range.rows().next().unwrap());
+        assert_eq!(bound.get("amount"), &DataType::String("12".to_string()));
+        assert_eq!(
+            bound.get("date"),
+            &DataType::String("2023-01-31".to_string())
+        );
+        assert_eq!(bound.get("note"), &DataType::Empty);
+    }
+
+    #[test]
+    fn test_missing_required_header() {
+        let range = sheet(&[&["Id", "Date", "Note"]]);
+        let err = ColumnBinding::find(&range, COLUMNS).unwrap_err();
+        assert!(err.to_string().contains("amount (Amount/Betrag)"));
     }
 }
@@ -1,41 +1,149 @@
-use chrono::{NaiveDate, NaiveDateTime};
+use anyhow::Result;
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime};
 use diesel::prelude::*;
 use dotenv::dotenv;
+use glob::glob;
 use regex::Regex;
 use std::env;
 
+use crate::query::text_match::register_regexp;
+
 pub fn establish_connection() -> SqliteConnection {
     dotenv().ok();
 
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    SqliteConnection::establish(&database_url)
-        .unwrap_or_else(|_| panic!("Error connecting to {}", database_url))
+    let mut connection = SqliteConnection::establish(&database_url)
+        .unwrap_or_else(|_| panic!("Error connecting to {}", database_url));
+    register_regexp(&mut connection).expect("Error registering the regexp function");
+    connection
+}
+
+// Expands the glob patterns, keeping plain file names as they are.
+pub fn expand_input_files(inputs: &[String]) -> Result<Vec<String>> {
+    let mut result = Vec::new();
+    for input in inputs {
+        if input.contains(['*', '?', '[']) {
+            let mut matched = Vec::new();
+            for path in glob(input)? {
+                matched.push(path?.to_string_lossy().into_owned());
+            }
+            if matched.is_empty() {
+                return Err(anyhow!("No input file matches '{}'!", input));
+            }
+            matched.sort();
+            result.extend(matched);
+        } else {
+            result.push(input.clone());
+        }
+    }
+    Ok(result)
 }
 
 pub fn to_date(date_string: Option<String>) -> Option<NaiveDate> {
     date_string.and_then(|x| NaiveDate::parse_from_str(x.as_ref(), "%Y-%m-%d").ok())
 }
 
+// Like to_date, but a malformed date is an error instead of a missing filter
+pub fn parse_date_arg(name: &str, date_string: &Option<String>) -> Result<Option<NaiveDate>> {
+    date_string
+        .as_ref()
+        .map(|text| {
+            NaiveDate::parse_from_str(text, "%Y-%m-%d").map_err(|_| {
+                anyhow!(
+                    "Invalid {} date: '{}', expected yyyy-mm-dd format!",
+                    name,
+                    text
+                )
+            })
+        })
+        .transpose()
+}
+
 pub fn to_string(date: Option<NaiveDate>) -> String {
     date.map_or_else(|| "".to_string(), |dt| dt.format("%Y-%m-%d").to_string())
 }
 
-pub fn extract_date(string: &Option<String>) -> Option<NaiveDate> {
-    lazy_static! {
-        static ref RE: Regex = Regex::new(r"(\d{4})\.(\d{2})\.(\d{2})").unwrap();
+// Patterns of the card usage dates inside the descriptions, with named d, m and optional y groups.
+pub const YMD_DOTTED: &str = r"(?P<y>\d{4})\.(?P<m>\d{2})\.(?P<d>\d{2})";
+pub const DMY_DOTTED: &str = r"(?P<d>\d{2})\.(?P<m>\d{2})\.(?P<y>\d{4})";
+pub const DM_DOTTED_TIME: &str = r"(?P<d>\d{2})\.(?P<m>\d{2})\.?\s*(?:UM\s*)?\d{2}[:.]\d{2}";
+pub const DMY_SLASH: &str = r"(?P<d>\d{2})/(?P<m>\d{2})(?:/(?P<y>\d{4}|\d{2}))?";
+pub const ISO_TIMESTAMP: &str = r"(?P<y>\d{4})-(?P<m>\d{2})-(?P<d>\d{2})[T ]\d{2}:\d{2}";
+
+// The built-in patterns can be referred by name, anything else is a regex.
+fn resolve_date_pattern(pattern: &str) -> &str {
+    match pattern {
+        "ymd-dotted" => YMD_DOTTED,
+        "dmy-dotted" => DMY_DOTTED,
+        "dm-dotted-time" => DM_DOTTED_TIME,
+        "dmy-slash" => DMY_SLASH,
+        "iso-timestamp" => ISO_TIMESTAMP,
+        _ => pattern,
+    }
+}
+
+// A card payment is booked within this many days after the spending
+const MAX_SPENDING_DELAY_DAYS: i64 = 30;
+
+// Finds the real spending date in a description. When the pattern has no year,
+// the latest year is used which doesn't put the date after the reference date.
+// With a reference date, other dates, like the date of an invoice, are ignored.
+pub struct DateExtractor {
+    patterns: Vec<Regex>,
+}
+
+impl DateExtractor {
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> Result<Self> {
+        let mut compiled = Vec::new();
+        for pattern in patterns {
+            let pattern = resolve_date_pattern(pattern.as_ref());
+            let regex = Regex::new(pattern)
+                .map_err(|e| anyhow!("Invalid date pattern '{}': {}", pattern, e))?;
+            let names: Vec<_> = regex.capture_names().flatten().collect();
+            if !names.contains(&"d") || !names.contains(&"m") {
+                return Err(anyhow!(
+                    "Date pattern '{}' needs named groups for the day (?P<d>..) and month (?P<m>..)",
+                    pattern
+                ));
+            }
+            compiled.push(regex);
+        }
+        Ok(DateExtractor { patterns: compiled })
     }
-    string.as_ref().and_then(|str| {
-        let caps = RE.captures(str)?;
-        let year = caps.get(1).unwrap().as_str();
-        let month = caps.get(2).unwrap().as_str();
-        let day = caps.get(3).unwrap().as_str();
 
-        NaiveDate::from_ymd_opt(
-            year.parse().expect("Number as year"),
-            month.parse().expect("Number as month"),
-            day.parse().expect("Number as day"),
-        )
-    })
+    pub fn extract(&self, text: &str, reference: Option<NaiveDate>) -> Option<NaiveDate> {
+        self.patterns.iter().find_map(|pattern| {
+            pattern
+                .captures_iter(text)
+                .find_map(|caps| to_extracted_date(&caps, reference))
+        })
+    }
+}
+
+fn to_extracted_date(caps: &regex::Captures, reference: Option<NaiveDate>) -> Option<NaiveDate> {
+    let day: u32 = caps.name("d")?.as_str().parse().ok()?;
+    let month: u32 = caps.name("m")?.as_str().parse().ok()?;
+    let date = match caps.name("y") {
+        Some(year) => {
+            let year: i32 = year.as_str().parse().ok()?;
+            let year = if year < 100 { year + 2000 } else { year };
+            NaiveDate::from_ymd_opt(year, month, day)?
+        }
+        None => {
+            let reference = reference?;
+            NaiveDate::from_ymd_opt(reference.year(), month, day)
+                .filter(|date| *date <= reference)
+                .or_else(|| NaiveDate::from_ymd_opt(reference.year() - 1, month, day))?
+        }
+    };
+    match reference {
+        Some(reference)
+            if date > reference || date < reference - Duration::days(MAX_SPENDING_DELAY_DAYS) =>
+        {
+            None
+        }
+        _ => Some(date),
+    }
 }
 
 pub fn parse_sqlite_date(value: &Option<String>) -> Option<NaiveDateTime> {
@@ -44,6 +152,7 @@ pub fn parse_sqlite_date(value: &Option<String>) -> Option<NaiveDateTime> {
         .and_then(|date_str| parse_date_2_format(date_str))
 }
 
+#[allow(clippy::manual_ok_err)]
 fn parse_date_2_format(value: &str) -> Option<NaiveDateTime> {
     if let Ok(ndt) = NaiveDateTime::parse_from_str(value, "%Y%m%d%H%M%S") {
         Some(ndt)
@@ -83,6 +192,31 @@ impl DenominatedValue {
         Self { value, denom }
     }
 
+    // Exact value of a decimal number, like '-12.50' -> -1250/100
+    pub fn parse_decimal(text: &str) -> Result<Self> {
+        let invalid = || anyhow!("Invalid amount: '{}'", text);
+        let trimmed = text.trim();
+        let (negative, digits) = match trimmed.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        if whole.is_empty() && fraction.is_empty()
+            || !whole
+                .chars()
+                .chain(fraction.chars())
+                .all(|c| c.is_ascii_digit())
+            || fraction.len() > 9
+        {
+            return Err(invalid());
+        }
+        let denom = 10_i64.pow(fraction.len() as u32);
+        let value: i64 = format!("{}{}", whole, fraction)
+            .parse()
+            .map_err(|_| invalid())?;
+        Ok(Self::new(if negative { -value } else { value }, denom))
+    }
+
     pub fn denominate_float(value: f64, denom: i32) -> Self {
         Self {
             value: (value * f64::from(denom)).round() as i64,
@@ -132,17 +266,79 @@ mod tests {
 
     #[test]
     fn test_extract_date_none() {
-        assert_eq!(extract_date(&None), None);
+        let extractor = DateExtractor::new(&[YMD_DOTTED]).unwrap();
+        assert_eq!(extractor.extract("XYZ. PD. 4488620465", None), None);
     }
 
     #[test]
     fn test_extract_date_string() {
+        let extractor = DateExtractor::new(&[YMD_DOTTED]).unwrap();
         assert_eq!(
-            extract_date(&Some("XYZ. PD.  2016.10.20 4488620465".to_string())),
+            extractor.extract("XYZ. PD.  2016.10.20 4488620465", None),
             NaiveDate::from_ymd_opt(2016, 10, 20)
         );
     }
 
+    #[test]
+    fn test_extract_date_without_year() {
+        let extractor = DateExtractor::new(&[DMY_DOTTED, DM_DOTTED_TIME, "dmy-slash"]).unwrap();
+        let reference = NaiveDate::from_ymd_opt(2017, 1, 3);
+        assert_eq!(
+            extractor.extract("POS K1 30.12. UM 14.22 BILLA", reference),
+            NaiveDate::from_ymd_opt(2016, 12, 30)
+        );
+        assert_eq!(
+            extractor.extract("CARD 02/01 TESCO", reference),
+            NaiveDate::from_ymd_opt(2017, 1, 2)
+        );
+        assert_eq!(
+            extractor.extract("CARD 28/12/16 TESCO", None),
+            NaiveDate::from_ymd_opt(2016, 12, 28)
+        );
+        assert_eq!(extractor.extract("CARD 02/01 TESCO", None), None);
+    }
+
+    #[test]
+    fn test_old_dates_are_ignored() {
+        let extractor = DateExtractor::new(&[YMD_DOTTED, DMY_DOTTED]).unwrap();
+        let reference = NaiveDate::from_ymd_opt(2023, 3, 2);
+        assert_eq!(
+            extractor.extract("Invoice 2021.11.15, paid 2023.02.28", reference),
+            NaiveDate::from_ymd_opt(2023, 2, 28)
+        );
+        assert_eq!(extractor.extract("Contract of 15.11.2021", reference), None);
+        assert_eq!(extractor.extract("Order 2023.03.20", reference), None);
+    }
+
+    #[test]
+    fn test_invalid_date_pattern() {
+        assert!(DateExtractor::new(&[r"(\d{2})\.(\d{2})"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_date_arg() {
+        assert_eq!(parse_date_arg("from", &None).unwrap(), None);
+        assert_eq!(
+            parse_date_arg("from", &Some("2023-03-31".to_owned())).unwrap(),
+            NaiveDate::from_ymd_opt(2023, 3, 31)
+        );
+        assert!(parse_date_arg("from", &Some("2023.03.31".to_owned())).is_err());
+    }
+
+    #[test]
+    fn test_parse_decimal() {
+        assert_eq!(
+            DenominatedValue::parse_decimal("-12.50").unwrap(),
+            DenominatedValue::new(-1250, 100)
+        );
+        assert_eq!(
+            DenominatedValue::parse_decimal("400").unwrap(),
+            DenominatedValue::new(400, 1)
+        );
+        assert!(DenominatedValue::parse_decimal("12,5").is_err());
+        assert!(DenominatedValue::parse_decimal("-").is_err());
+    }
+
     #[test]
     fn test_guid_formatting() {
         assert_eq!(